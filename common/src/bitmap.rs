@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::vec::Vec;
 
-const NEW_U64: u128 = 0xffffffffffffffff; // 2^64
+const NEW_U64: u128 = u128::MAX; // 2^128 - 1, all slots of the word are set
 const MAX_LEN: usize = 128; // 64
 const NEW_BIT: u128 = 0x0000000000000001; // 1
 
@@ -43,7 +43,7 @@ impl BitMap {
             }
 
             for j in 0..MAX_LEN {
-                if (self.vector[0 as usize] & (1 << j)) == 0 {
+                if (self.vector[i as usize] & (1 << j)) == 0 {
                     self.vector[i as usize] |= 1 << j;
                     return i * MAX_LEN + j;
                 }
@@ -51,7 +51,7 @@ impl BitMap {
         }
         self.vector.push(NEW_BIT);
 
-        self.vector.len() * MAX_LEN + 1
+        (self.vector.len() - 1) * MAX_LEN
     }
 
     #[inline]
@@ -61,8 +61,6 @@ impl BitMap {
         }
         let i = (slot / MAX_LEN) as usize;
         let j = (slot % MAX_LEN) as usize;
-        println!("{} {} {}", self.vector[i], i, j);
-
         self.vector[i] ^= (1 << j);
     }
 } //}}}
@@ -0,0 +1,35 @@
+const POLY: u32 = 0xedb88320; // reversed crc-32 (ieee) polynomial
+
+/// crc-32 (ieee) checksum of the data
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// crc-32 (ieee) checksum of the data written in pieces
+#[derive(Copy, Clone, Debug)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { crc: !0u32 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        //{{{
+        for byte in data {
+            self.crc ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (!(self.crc & 1)).wrapping_add(1);
+                self.crc = (self.crc >> 1) ^ (POLY & mask);
+            }
+        }
+    } //}}}
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
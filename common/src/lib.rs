@@ -1 +1,2 @@
 pub mod bitmap;
+pub mod crc32;
@@ -1,7 +1,7 @@
+use rust_decimal::prelude::Zero;
 use rust_decimal::Decimal;
 use rust_decimal_macros::*;
 use std::fmt;
-use std::time::Instant;
 use serde::{Deserialize, Serialize};
 
 
@@ -10,8 +10,28 @@ use serde::{Deserialize, Serialize};
 pub enum OrderOp {
     #[default]
     Limit, // limit order type
-    Market, // market order type
-    Cancel, // cancel order type
+    Market,     // market order type
+    Cancel,     // cancel order type
+    StopMarket, // market order triggered by the last price
+    StopLimit,  // limit order triggered by the last price
+    Amend,      // amend the price or quantity of the order in the orderbook
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub enum TimeInForce {
+    #[default]
+    GTC, // good till cancel, the remain part rests in the orderbook
+    IOC, // immediate or cancel, the remain part is canceled
+    FOK, // fill or kill, the order is rejected if it can not be filled entirely
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
+#[allow(dead_code)]
+pub enum QtyType {
+    #[default]
+    Base, // order qty is base currency qty
+    Quote, // order qty is quote currency funds, only for market order
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
@@ -40,23 +60,30 @@ pub enum TradeType {
     #[default]
     SimpleTrade, // simple trade trade record type
     CancelTrade, // cancel order trade record type
+    RejectTrade, // market order unfillable part auto cancel record type
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct OrderInfo {
     //{{{
-    pub id: u64,                  // order id
-    pub uid: u64,                 // order user id
-    pub op: OrderOp,              // order opreation
-    pub side: OrderSide,          // order side
-    pub price: Decimal,           // order price
-    pub avg_trade_price: Decimal, // order tarde average price
-    pub raw_qty: Decimal,         // order quantity
+    pub id: u64,                    // order id
+    pub uid: u64,                   // order user id
+    pub op: OrderOp,                // order opreation
+    pub time_in_force: TimeInForce, // limit order time in force
+    pub post_only: bool,            // limit order only can be maker
+    pub qty_type: QtyType,          // market order qty is base qty or quote funds
+    pub side: OrderSide,            // order side
+    pub price: Decimal,             // order price
+    pub trigger_price: Decimal,     // stop order trigger price
+    pub avg_trade_price: Decimal,   // order tarde average price
+    pub raw_qty: Decimal,           // order quantity, quote funds if qty_type is quote
     pub remain_qty: Decimal,
-    pub trade_qty: Decimal,      // order traded quantity
-    pub trade_oppo_qty: Decimal, // order traded oppo quantity
-    pub status: OrderStatus,     // current order status
+    pub display_qty: Decimal, // iceberg order visible qty, 0 if not iceberg order
+    pub hidden_qty: Decimal,  // iceberg order hidden reserve qty
+    pub trade_qty: Decimal,   // order traded base quantity
+    pub trade_oppo_qty: Decimal, // order traded quote quantity
+    pub status: OrderStatus,  // current order status
     pub taker_fee_rate: Decimal, // order taker fee rate
     pub maker_fee_rate: Decimal, // order maker fee rate
     pub fee: Decimal,
@@ -65,7 +92,7 @@ pub struct OrderInfo {
 
 impl fmt::Display for OrderInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "id:{}\nuid:{}\nop:{:?}\nside:{:?}\nprice:{}\navg_trade_price::{}\nraw_qty::{}\nremain_qty:{}\ntrade_qty:{}\ntrade_oppo_qty:{}\nstatus:{:?}\ntaker_fee:{}\nmaker_fee:{}\nfee:{}\ncurr_slot:{}\npre_slot:{}\nnext_slot:{}\nused:{}\n", self.id, self.uid, self.op, self.side, self.price, self.avg_trade_price, self.raw_qty, self.remain_qty, self.trade_qty, self.trade_oppo_qty, self.status, self.taker_fee_rate,  self.maker_fee_rate, self.fee, self.logic.curr_slot, self.logic.pre_slot, self.logic.next_slot, self.logic.used)
+        write!(f, "id:{}\nuid:{}\nop:{:?}\ntime_in_force:{:?}\npost_only:{}\nqty_type:{:?}\nside:{:?}\nprice:{}\ntrigger_price:{}\navg_trade_price::{}\nraw_qty::{}\nremain_qty:{}\ndisplay_qty:{}\nhidden_qty:{}\ntrade_qty:{}\ntrade_oppo_qty:{}\nstatus:{:?}\ntaker_fee:{}\nmaker_fee:{}\nfee:{}\ncurr_slot:{}\npre_slot:{}\nnext_slot:{}\nused:{}\n", self.id, self.uid, self.op, self.time_in_force, self.post_only, self.qty_type, self.side, self.price, self.trigger_price, self.avg_trade_price, self.raw_qty, self.remain_qty, self.display_qty, self.hidden_qty, self.trade_qty, self.trade_oppo_qty, self.status, self.taker_fee_rate,  self.maker_fee_rate, self.fee, self.logic.curr_slot, self.logic.pre_slot, self.logic.next_slot, self.logic.used)
     }
 }
 #[cfg(test)]
@@ -87,42 +114,71 @@ pub struct OrderLogic {
     pub used: bool,
 }
 
-#[allow(dead_code)]
-enum TradeError {
-    OrderQtyIllegal,
-    OrderPriceIllegal,
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TradeError {
+    OrderQtyIllegal,      // order qty is zero, negative, off the qty step or out of range
+    OrderPriceIllegal,    // order price is zero, negative, off the price tick or above the max
+    OrderNotionalIllegal, // order value is less than the min notional or out of decimal range
+    OrderSideIllegal,     // order side is not the side of the order in the orderbook
+    OrderOpIllegal,       // order operation is not supported here
+    OrderNotFound,        // there is no such order of the user
+    OrderIdDuplicate,     // the order id is already in the orderbook
+    OrderBookFull,        // there is no free order slot in the orderbook
+    JournalFailed,        // the message can not be journaled, the orderbook stops
+    SnapshotFailed,       // the snapshot can not be written
 }
 
+impl fmt::Display for TradeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            TradeError::OrderQtyIllegal => "order qty illegal",
+            TradeError::OrderPriceIllegal => "order price illegal",
+            TradeError::OrderNotionalIllegal => "order notional illegal",
+            TradeError::OrderSideIllegal => "order side illegal",
+            TradeError::OrderOpIllegal => "order operation illegal",
+            TradeError::OrderNotFound => "order not found",
+            TradeError::OrderIdDuplicate => "order id duplicate",
+            TradeError::OrderBookFull => "orderbook is full",
+            TradeError::JournalFailed => "journal failed",
+            TradeError::SnapshotFailed => "snapshot failed",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for TradeError {}
+
 #[allow(dead_code)]
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct TradeRecord {
     //{{{
-    trade_id: u64, // unique trade record id
-
-    bid_order_id: u64,       // bid order id
-    bid_uid: u64,            // user of the bid order
-    bid_type: OrderOp,       // bid order type
-    bid_raw_qty: Decimal,    // bid order raw quantity
-    bid_remain_qty: Decimal, // bid order remain quantity
-    bid_raw_price: Decimal,  // bid order raw quantity
-    bid_avg_price: Decimal,  // bid order trade avg price
-    bid_fee: Decimal,
-
-    ask_order_id: u64,       // ask order id
-    ask_uid: u64,            // user of the ask order
-    ask_type: OrderOp,       // ask order type
-    ask_raw_qty: Decimal,    // ask order raw quantity
-    ask_remain_qty: Decimal, // ask order remain quantity
-    ask_raw_price: Decimal,  // ask order raw price
-    ask_avg_price: Decimal,  // ask order trade avg price
-    ask_fee: Decimal,
-
-    trade_qty: Decimal,          // trade qty
-    trade_price: Decimal,        // trade price
-    trade_oppo_qty: Decimal,     // trade_oppo_qty = trade_qty * trade_price
-    trade_unfreeze_qty: Decimal, // taker order should be unfreeze qty
-    time_stamp: u64,             // trade timestap
-    trade_type: TradeType,
+    pub trade_id: u64, // unique trade record id in the market
+
+    pub bid_order_id: u64,       // bid order id
+    pub bid_uid: u64,            // user of the bid order
+    pub bid_type: OrderOp,       // bid order type
+    pub bid_raw_qty: Decimal,    // bid order raw quantity
+    pub bid_remain_qty: Decimal, // bid order remain quantity
+    pub bid_raw_price: Decimal,  // bid order raw quantity
+    pub bid_avg_price: Decimal,  // bid order trade avg price
+    pub bid_fee: Decimal,
+
+    pub ask_order_id: u64,       // ask order id
+    pub ask_uid: u64,            // user of the ask order
+    pub ask_type: OrderOp,       // ask order type
+    pub ask_raw_qty: Decimal,    // ask order raw quantity
+    pub ask_remain_qty: Decimal, // ask order remain quantity
+    pub ask_raw_price: Decimal,  // ask order raw price
+    pub ask_avg_price: Decimal,  // ask order trade avg price
+    pub ask_fee: Decimal,
+
+    pub trade_qty: Decimal,           // trade qty
+    pub trade_price: Decimal,         // trade price
+    pub trade_oppo_qty: Decimal,      // trade_oppo_qty = trade_qty * trade_price
+    pub trade_unfreeze_qty: Decimal,  // taker order should be unfreeze qty
+    pub trade_unfreeze_type: QtyType, // trade_unfreeze_qty is base qty or quote funds
+    pub time_stamp: u64,              // trade time in milliseconds
+    pub trade_type: TradeType,
 } //}}}
 
 impl Default for TradeRecord {
@@ -151,17 +207,13 @@ impl Default for TradeRecord {
             trade_price: zero,
             trade_oppo_qty: zero,
             trade_unfreeze_qty: zero,
+            trade_unfreeze_type: QtyType::Base,
             time_stamp: 0u64,
             trade_type: TradeType::SimpleTrade,
         }
     }
 } //}}}
 
-pub(crate) fn gen_trade_id() -> u64 {
-    // TODO:
-    1u64
-}
-
 impl OrderInfo {
     #[inline]
     pub fn new(
@@ -177,12 +229,18 @@ impl OrderInfo {
             id: id,
             uid: uid,
             op: OrderOp::Limit,
+            time_in_force: TimeInForce::GTC,
+            post_only: false,
+            qty_type: QtyType::Base,
             side: side,
             price: price,
+            trigger_price: dec!(0),
             avg_trade_price: dec!(0),
             raw_qty: qty,
             trade_qty: dec!(0),
             remain_qty: qty,
+            display_qty: dec!(0),
+            hidden_qty: dec!(0),
             trade_oppo_qty: dec!(0),
             status: OrderStatus::PaddingTrade,
             taker_fee_rate: taker_fee,
@@ -197,287 +255,164 @@ impl OrderInfo {
         } //}}}
     }
 
-    // gennerate new unique trade record id
-    pub fn trade(&mut self, taker: &mut OrderInfo) -> Option<TradeRecord> {
+    // gennerate new unique trade record id,
+    // the qty bought by the quote funds is rounded down to the lot size, zero means no limit
+    pub fn trade(
+        &mut self,
+        taker: &mut OrderInfo,
+        lot_size: Decimal,
+    ) -> Result<TradeRecord, TradeError> {
         //{{{
-        // ensure the taker order is limit type
-        assert_eq!(self.op, OrderOp::Limit);
-
-        match taker.op {
-            OrderOp::Limit => {
-                //{{{
-                // colc trade qty
-                let trade_qty = if self.remain_qty > taker.remain_qty {
+        // ensure the maker order is limit type
+        if self.op != OrderOp::Limit {
+            return Err(TradeError::OrderOpIllegal);
+        }
+
+        // every amount is calculated before the orders are changed,
+        // the trade is refused as a whole if any of them overflows
+        let overflow = TradeError::OrderNotionalIllegal;
+
+        // colc trade qty, trade qty is always base qty
+        let trade_qty = match (taker.op, taker.qty_type) {
+            (OrderOp::Limit, _) | (OrderOp::Market, QtyType::Base) => {
+                if self.remain_qty > taker.remain_qty {
                     taker.remain_qty
                 } else {
                     self.remain_qty
-                };
-
-                assert!(trade_qty > dec!(0));
-                self.trade_qty = self.trade_qty + trade_qty;
-                self.remain_qty = self.remain_qty - trade_qty;
-
-                taker.trade_qty = taker.trade_qty + trade_qty;
-                taker.remain_qty = taker.remain_qty - trade_qty;
-
-                let oppo_qty = trade_qty * self.price;
-
-                match self.side {
-                    OrderSide::Ask => {
-                        self.fee = self.fee + oppo_qty * self.maker_fee_rate;
-                        taker.fee = taker.fee + trade_qty * taker.taker_fee_rate;
-                    }
-                    OrderSide::Bid => {
-                        self.fee = self.fee + trade_qty * self.maker_fee_rate;
-                        taker.fee = taker.fee + oppo_qty * taker.taker_fee_rate;
-                    }
                 }
-
-                self.trade_oppo_qty = self.trade_oppo_qty + oppo_qty;
-
-                taker.trade_oppo_qty = taker.trade_oppo_qty + oppo_qty;
-
-                self.avg_trade_price = self.trade_oppo_qty / self.trade_qty;
-
-                taker.avg_trade_price = taker.trade_oppo_qty / taker.trade_qty;
-
-                // colc self and taker order status.
-                self.status = if self.raw_qty == self.trade_qty {
-                    self.logic.used = false;
-                    OrderStatus::AllTrade
-                } else {
-                    OrderStatus::PartTrade
-                };
-
-                taker.status = if taker.raw_qty == taker.trade_qty {
-                    taker.logic.used = false;
-                    OrderStatus::AllTrade
-                } else {
-                    OrderStatus::PartTrade
-                };
-
-                let trade_id = gen_trade_id();
-                let bid_order = if self.side == OrderSide::Ask {
-                    *taker
-                } else {
-                    *self
-                };
-
-                let ask_order = if self.side == OrderSide::Ask {
-                    *self
+            }
+            (OrderOp::Market, QtyType::Quote) => {
+                let mut qty = taker.remain_qty.checked_div(self.price).ok_or(overflow)?;
+                if !lot_size.is_zero() {
+                    // the funds only buy whole lots, the funds left are rejected by the orderbook
+                    qty = (qty / lot_size).floor() * lot_size;
+                }
+                if self.remain_qty > qty {
+                    qty
                 } else {
-                    *taker
-                };
-                let trade_unfreeze_qty =
-                    if taker.side == OrderSide::Bid && taker.op == OrderOp::Limit {
-                        trade_qty * (taker.price - self.price)
-                    } else {
-                        dec!(0)
-                    };
-
-                Some(TradeRecord {
-                    trade_id: trade_id,
-                    bid_order_id: bid_order.id,
-                    bid_uid: bid_order.uid,
-                    bid_type: OrderOp::Limit,
-                    bid_raw_qty: bid_order.raw_qty,
-                    bid_remain_qty: bid_order.remain_qty,
-                    bid_raw_price: bid_order.price,
-                    bid_avg_price: bid_order.avg_trade_price,
-                    bid_fee: bid_order.fee,
-
-                    ask_order_id: ask_order.id,
-                    ask_uid: ask_order.uid,
-                    ask_type: OrderOp::Limit,
-                    ask_raw_qty: ask_order.raw_qty,
-                    ask_remain_qty: ask_order.remain_qty,
-                    ask_raw_price: ask_order.price,
-                    ask_avg_price: ask_order.avg_trade_price,
-                    ask_fee: ask_order.fee,
-
-                    time_stamp: Instant::now().elapsed().as_secs(),
-
-                    trade_qty: trade_qty,
-                    trade_price: self.price,
-                    trade_oppo_qty: oppo_qty,
-                    trade_unfreeze_qty: trade_unfreeze_qty,
-
-                    trade_type: TradeType::SimpleTrade,
-                })
-            } //}}}
-
-            OrderOp::Market => {
-                match taker.side {
-                    OrderSide::Ask => {
-                        //{{{
-                        // colc trade qty
-                        let trade_qty = if self.remain_qty > taker.remain_qty {
-                            taker.remain_qty
-                        } else {
-                            self.remain_qty
-                        };
-
-                        self.trade_qty = self.trade_qty + trade_qty;
-                        self.remain_qty = self.remain_qty - trade_qty;
-                        self.fee = self.fee + trade_qty * self.maker_fee_rate;
-
-                        taker.trade_qty = taker.trade_qty + trade_qty;
-                        taker.remain_qty = taker.remain_qty - trade_qty;
-
-                        let oppo_qty = trade_qty * self.price;
-
-                        self.trade_oppo_qty = self.trade_oppo_qty + oppo_qty;
-
-                        taker.trade_oppo_qty = taker.trade_oppo_qty + oppo_qty;
-
-                        taker.fee = taker.fee + oppo_qty * taker.taker_fee_rate;
-
-                        self.avg_trade_price = self.trade_qty / self.trade_oppo_qty;
-
-                        taker.avg_trade_price = taker.trade_qty / taker.trade_oppo_qty;
-
-                        // colc self and taker order status.
-                        self.status = if self.raw_qty == self.trade_qty {
-                            OrderStatus::AllTrade
-                        } else {
-                            OrderStatus::PartTrade
-                        };
-
-                        taker.status = if taker.raw_qty == taker.trade_qty {
-                            OrderStatus::AllTrade
-                        } else {
-                            OrderStatus::PartTrade
-                        };
-
-                        let trade_id = gen_trade_id();
-                        let bid_order = if self.side == OrderSide::Ask {
-                            *taker
-                        } else {
-                            *self
-                        };
-
-                        let ask_order = if self.side == OrderSide::Ask {
-                            *self
-                        } else {
-                            *taker
-                        };
-
-                        Some(TradeRecord {
-                            trade_id: trade_id,
-                            bid_order_id: bid_order.id,
-                            bid_uid: bid_order.uid,
-                            bid_type: OrderOp::Limit,
-                            bid_raw_qty: bid_order.raw_qty,
-                            bid_remain_qty: bid_order.remain_qty,
-                            bid_raw_price: bid_order.price,
-                            bid_avg_price: bid_order.avg_trade_price,
-                            bid_fee: bid_order.fee,
-
-                            ask_order_id: ask_order.id,
-                            ask_uid: ask_order.uid,
-                            ask_type: OrderOp::Limit,
-                            ask_raw_qty: ask_order.raw_qty,
-                            ask_remain_qty: ask_order.remain_qty,
-                            ask_raw_price: ask_order.price,
-                            ask_avg_price: ask_order.avg_trade_price,
-                            ask_fee: ask_order.fee,
-
-                            time_stamp: Instant::now().elapsed().as_secs(),
-
-                            trade_qty: trade_qty,
-                            trade_price: self.price,
-                            trade_oppo_qty: oppo_qty,
-                            trade_unfreeze_qty: dec!(0),
-                            trade_type: TradeType::SimpleTrade,
-                        });
-                    } //}}}
-                    OrderSide::Bid => {
-                        //{{{
-                        let trade_qty = if self.remain_qty * self.price > taker.remain_qty {
-                            taker.remain_qty / self.price
-                        } else {
-                            self.remain_qty
-                        };
-
-                        let trade_oppo_qty = trade_qty * self.price;
-
-                        self.trade_qty = self.trade_qty + trade_qty;
-                        self.remain_qty = self.remain_qty - trade_qty;
-
-                        taker.trade_qty = taker.trade_qty + trade_oppo_qty;
-                        taker.remain_qty = taker.remain_qty - trade_oppo_qty;
-
-                        self.trade_oppo_qty = self.trade_oppo_qty + trade_oppo_qty;
-                        taker.trade_oppo_qty = taker.trade_oppo_qty + trade_qty;
-
-                        self.avg_trade_price = self.trade_qty / self.trade_oppo_qty;
-                        taker.avg_trade_price = taker.trade_oppo_qty / taker.trade_qty;
-
-                        self.fee = self.fee + trade_oppo_qty * self.maker_fee_rate;
-                        taker.fee = taker.fee + trade_qty * taker.taker_fee_rate;
-
-                        self.status = if self.raw_qty == self.trade_qty {
-                            OrderStatus::AllTrade
-                        } else {
-                            OrderStatus::PartTrade
-                        };
-
-                        taker.status = if taker.raw_qty == self.trade_qty {
-                            OrderStatus::AllTrade
-                        } else {
-                            OrderStatus::PartTrade
-                        };
-
-                        let trade_id = gen_trade_id();
-                        let bid_order = if self.side == OrderSide::Ask {
-                            *taker
-                        } else {
-                            *self
-                        };
-
-                        let ask_order = if self.side == OrderSide::Ask {
-                            *self
-                        } else {
-                            *taker
-                        };
-
-                        Some(TradeRecord {
-                            trade_id: trade_id,
-                            bid_order_id: bid_order.id,
-                            bid_uid: bid_order.uid,
-                            bid_type: OrderOp::Limit,
-                            bid_raw_qty: bid_order.raw_qty,
-                            bid_remain_qty: bid_order.remain_qty,
-                            bid_raw_price: bid_order.price,
-                            bid_avg_price: bid_order.avg_trade_price,
-                            bid_fee: bid_order.fee,
-
-                            ask_order_id: ask_order.id,
-                            ask_uid: ask_order.uid,
-                            ask_type: OrderOp::Limit,
-                            ask_raw_qty: ask_order.raw_qty,
-                            ask_remain_qty: ask_order.remain_qty,
-                            ask_raw_price: ask_order.price,
-                            ask_avg_price: ask_order.avg_trade_price,
-                            ask_fee: ask_order.fee,
-                            time_stamp: Instant::now().elapsed().as_secs(),
-
-                            trade_qty: trade_qty,
-                            trade_price: self.price,
-                            trade_oppo_qty: trade_oppo_qty,
-                            trade_unfreeze_qty: dec!(0),
-                            trade_type: TradeType::SimpleTrade,
-                        });
-                    } //}}}
+                    self.remain_qty
                 }
-                None
             }
+            _ => return Err(TradeError::OrderOpIllegal),
+        };
 
-            _ => {
-                println! {"unsupport order operation"}
-                None
-            }
+        if trade_qty.is_zero() || !trade_qty.is_sign_positive() {
+            return Err(TradeError::OrderQtyIllegal);
         }
+
+        let oppo_qty = trade_qty.checked_mul(self.price).ok_or(overflow)?;
+
+        // the bid order fee is base qty, the ask order fee is quote qty
+        let (maker_fee, taker_fee) = match self.side {
+            OrderSide::Ask => (
+                oppo_qty.checked_mul(self.maker_fee_rate),
+                trade_qty.checked_mul(taker.taker_fee_rate),
+            ),
+            OrderSide::Bid => (
+                trade_qty.checked_mul(self.maker_fee_rate),
+                oppo_qty.checked_mul(taker.taker_fee_rate),
+            ),
+        };
+        let maker_fee = maker_fee
+            .and_then(|fee| self.fee.checked_add(fee))
+            .ok_or(overflow)?;
+        let taker_fee = taker_fee
+            .and_then(|fee| taker.fee.checked_add(fee))
+            .ok_or(overflow)?;
+
+        let maker_trade_qty = self.trade_qty.checked_add(trade_qty).ok_or(overflow)?;
+        let taker_trade_qty = taker.trade_qty.checked_add(trade_qty).ok_or(overflow)?;
+        let maker_oppo_qty = self.trade_oppo_qty.checked_add(oppo_qty).ok_or(overflow)?;
+        let taker_oppo_qty = taker.trade_oppo_qty.checked_add(oppo_qty).ok_or(overflow)?;
+
+        // the limit bid order unfreezes the quote funds saved by the better price,
+        // other orders unfreeze the unspent remain in their own qty type
+        let (trade_unfreeze_qty, trade_unfreeze_type) =
+            if taker.side == OrderSide::Bid && taker.op == OrderOp::Limit {
+                let saved = (taker.price - self.price).checked_mul(trade_qty);
+                (saved.ok_or(overflow)?, QtyType::Quote)
+            } else {
+                (dec!(0), taker.qty_type)
+            };
+
+        self.trade_qty = maker_trade_qty;
+        self.remain_qty = self.remain_qty - trade_qty;
+
+        taker.trade_qty = taker_trade_qty;
+        taker.remain_qty = match taker.qty_type {
+            QtyType::Quote if taker.op == OrderOp::Market => taker.remain_qty - oppo_qty,
+            _ => taker.remain_qty - trade_qty,
+        };
+
+        self.fee = maker_fee;
+        taker.fee = taker_fee;
+
+        self.trade_oppo_qty = maker_oppo_qty;
+
+        taker.trade_oppo_qty = taker_oppo_qty;
+
+        self.avg_trade_price = self.trade_oppo_qty / self.trade_qty;
+
+        taker.avg_trade_price = taker.trade_oppo_qty / taker.trade_qty;
+
+        // colc self and taker order status.
+        self.status = if self.remain_qty.is_zero() {
+            self.logic.used = false;
+            OrderStatus::AllTrade
+        } else {
+            OrderStatus::PartTrade
+        };
+
+        taker.status = if taker.remain_qty.is_zero() {
+            taker.logic.used = false;
+            OrderStatus::AllTrade
+        } else {
+            OrderStatus::PartTrade
+        };
+
+        let bid_order = if self.side == OrderSide::Ask {
+            *taker
+        } else {
+            *self
+        };
+
+        let ask_order = if self.side == OrderSide::Ask {
+            *self
+        } else {
+            *taker
+        };
+
+        // the trade id and the time stamp are assigned by the orderbook
+        Ok(TradeRecord {
+            trade_id: 0,
+            bid_order_id: bid_order.id,
+            bid_uid: bid_order.uid,
+            bid_type: bid_order.op,
+            bid_raw_qty: bid_order.raw_qty,
+            bid_remain_qty: bid_order.remain_qty,
+            bid_raw_price: bid_order.price,
+            bid_avg_price: bid_order.avg_trade_price,
+            bid_fee: bid_order.fee,
+
+            ask_order_id: ask_order.id,
+            ask_uid: ask_order.uid,
+            ask_type: ask_order.op,
+            ask_raw_qty: ask_order.raw_qty,
+            ask_remain_qty: ask_order.remain_qty,
+            ask_raw_price: ask_order.price,
+            ask_avg_price: ask_order.avg_trade_price,
+            ask_fee: ask_order.fee,
+
+            time_stamp: 0,
+
+            trade_qty: trade_qty,
+            trade_price: self.price,
+            trade_oppo_qty: oppo_qty,
+            trade_unfreeze_qty: trade_unfreeze_qty,
+            trade_unfreeze_type: trade_unfreeze_type,
+
+            trade_type: TradeType::SimpleTrade,
+        })
     } //}}}
 
     pub fn cancel(&mut self) -> Option<TradeRecord> {
@@ -486,12 +421,43 @@ impl OrderInfo {
             return None;
         }
 
-        let trade_id = gen_trade_id();
+        Some(self.cancel_remain())
+    } //}}}
+
+    // cancel the remain part of the order whether it is in the orderbook or not
+    pub fn cancel_remain(&mut self) -> TradeRecord {
+        //{{{
+        // the hidden reserve of iceberg order is canceled together
+        self.remain_qty += self.hidden_qty;
+        self.hidden_qty = dec!(0);
+
         self.logic.used = false;
+        self.status = if self.trade_qty.is_zero() {
+            OrderStatus::AllCancel
+        } else {
+            OrderStatus::PartCancel
+        };
 
+        self.remain_record(TradeType::CancelTrade)
+    } //}}}
+
+    // reject the remain part of market order which can not be filled,
+    // the remain base qty or quote funds of the order qty type should be unfreezed
+    pub fn reject_remain(&mut self) -> TradeRecord {
+        //{{{
+        self.logic.used = false;
+        self.status = OrderStatus::AutoCancel;
+
+        let mut record = self.remain_record(TradeType::RejectTrade);
+        record.trade_unfreeze_qty = self.remain_qty;
+        record.trade_unfreeze_type = self.qty_type;
+        record
+    } //}}}
+
+    fn remain_record(&self, trade_type: TradeType) -> TradeRecord {
+        //{{{
         match self.side {
-            OrderSide::Ask => Some(TradeRecord {
-                trade_id: trade_id,
+            OrderSide::Ask => TradeRecord {
                 ask_order_id: self.id,
                 ask_uid: self.uid,
                 ask_type: self.op,
@@ -500,10 +466,10 @@ impl OrderInfo {
                 ask_raw_price: self.price,
                 ask_avg_price: self.avg_trade_price,
                 ask_fee: self.fee,
-                trade_type: TradeType::CancelTrade,
+                trade_type: trade_type,
                 ..Default::default()
-            }),
-            OrderSide::Bid => Some(TradeRecord {
+            },
+            OrderSide::Bid => TradeRecord {
                 bid_order_id: self.id,
                 bid_uid: self.uid,
                 bid_type: self.op,
@@ -512,9 +478,9 @@ impl OrderInfo {
                 bid_raw_price: self.price,
                 bid_avg_price: self.avg_trade_price,
                 bid_fee: self.fee,
-                trade_type: TradeType::CancelTrade,
+                trade_type: trade_type,
                 ..Default::default()
-            }),
+            },
         }
     } //}}}
 }
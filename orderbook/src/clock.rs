@@ -0,0 +1,53 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The time source of the orderbook, in milliseconds since the unix epoch
+pub trait Clock: fmt::Debug + Send {
+    fn now(&self) -> u64;
+}
+
+/// The wall clock
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+}
+
+/// The clock which only moves when it is told to, the clones share the same time
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    time: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(time: u64) -> ManualClock {
+        ManualClock {
+            time: Arc::new(AtomicU64::new(time)),
+        }
+    }
+
+    pub fn set(&self, time: u64) {
+        self.time.store(time, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.time.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.time.load(Ordering::SeqCst)
+    }
+}
+
+pub(crate) fn default_clock() -> Box<dyn Clock> {
+    Box::new(SystemClock)
+}
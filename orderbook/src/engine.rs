@@ -0,0 +1,471 @@
+use crate::journal::Journal;
+use crate::ticker::{RollingTicker, Ticker};
+use crate::{MatchResult, Msg, OrderBook};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use order::proto::TradeError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::thread::{self, JoinHandle};
+
+/// How the orderbooks are scheduled on the worker threads
+#[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
+pub enum EngineMode {
+    #[default]
+    Pinned, // every orderbook is pinned to its own thread
+    Pool(usize), // orderbooks are sharded across a fixed number of threads
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EngineError {
+    MarketExist,    // the market is already in the engine
+    MarketNotFound, // there is no such market in the engine
+    EngineClosed,   // the worker thread of the market is gone
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            EngineError::MarketExist => "market already exist",
+            EngineError::MarketNotFound => "market not found",
+            EngineError::EngineClosed => "engine closed",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+/// The match result of one message, tagged with the market of the orderbook
+pub type EngineOutput = (String, Result<MatchResult, TradeError>);
+
+enum Command {
+    Msg(String, Msg),                        // route the message to the orderbook
+    AddMarket(OrderBook, Option<Journal>),   // take over the orderbook and its journal
+    RemoveMarket(String, Sender<OrderBook>), // give back the orderbook
+    Ticker(String, u64, Sender<Ticker>),     // query the ticker of the market at the time
+    Closed,                                  // stop the worker thread
+}
+
+struct Worker {
+    sender: Sender<Command>,
+    handle: JoinHandle<()>,
+    market_num: usize, // number of orderbooks on this worker
+}
+
+// the orderbook of one market owned by the worker thread
+struct Market {
+    book: OrderBook,
+    journal: Option<Journal>, // the messages are journaled before they are handled
+    halted: bool,             // the journal failed, the messages are not handled any more
+    ticker: RollingTicker,    // 24h statistics of the handled messages
+}
+
+/// The match engine owns the orderbooks of many markets and routes the messages to them.
+/// The messages of one market are always handled by the same thread in arrival order.
+/// The failure of the snapshot child is sent to the output channel after the batch in which
+/// it finished, the child is waited for before its orderbook is removed.
+pub struct MatchEngine {
+    mode: EngineMode,
+    workers: Vec<Option<Worker>>, // worker slots, the removed pinned worker leaves None
+    routes: HashMap<String, usize>, // market -> worker slot
+    output: Sender<EngineOutput>, // match results of all markets
+}
+
+impl MatchEngine {
+    pub fn new(mode: EngineMode, output: Sender<EngineOutput>) -> MatchEngine {
+        //{{{
+        let mut engine = MatchEngine {
+            mode: mode,
+            workers: Vec::new(),
+            routes: HashMap::new(),
+            output: output,
+        };
+        if let EngineMode::Pool(worker_num) = mode {
+            for _ in 0..worker_num.max(1) {
+                let worker = engine.spawn_worker();
+                engine.workers.push(Some(worker));
+            }
+        }
+        engine
+    } //}}}
+
+    fn spawn_worker(&self) -> Worker {
+        //{{{
+        let (sender, recv): (Sender<Command>, Receiver<Command>) = unbounded();
+        let output = self.output.clone();
+        let handle = thread::spawn(move || {
+            let mut markets: HashMap<String, Market> = HashMap::new();
+            loop {
+                let mut command = match recv.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                };
+
+                // the messages already sent are journaled and fsync'd as one batch
+                let mut batch = Vec::new();
+                while let Some(Command::Msg(market, msg)) = command {
+                    batch.push((market, msg));
+                    command = recv.try_recv().ok();
+                }
+                run_batch(&mut markets, batch, &output);
+
+                match command {
+                    Some(Command::AddMarket(book, journal)) => {
+                        let market = Market {
+                            book: book,
+                            journal: journal,
+                            halted: false,
+                            ticker: RollingTicker::new(),
+                        };
+                        markets.insert(market.book.market().to_owned(), market);
+                    }
+
+                    Some(Command::RemoveMarket(name, reply)) => {
+                        if let Some(mut market) = markets.remove(&name) {
+                            report_snapshot(&name, &mut market.book, true, &output);
+                            let _ = reply.send(market.book);
+                        }
+                    }
+
+                    Some(Command::Ticker(name, now, reply)) => {
+                        if let Some(market) = markets.get_mut(&name) {
+                            let _ = reply.send(market.ticker.ticker(&market.book, now));
+                        }
+                    }
+
+                    Some(Command::Closed) => {
+                        for (name, market) in markets.iter_mut() {
+                            report_snapshot(name, &mut market.book, true, &output);
+                        }
+                        break;
+                    }
+
+                    Some(Command::Msg(..)) | None => {}
+                }
+            }
+        });
+        Worker {
+            sender: sender,
+            handle: handle,
+            market_num: 0,
+        }
+    } //}}}
+
+    /// add the orderbook to the engine, its market must be unique.
+    /// with the journal, the messages of the market are written and fsync'd before they are
+    /// matched, the market stops matching if its journal fails
+    pub fn add_market(
+        &mut self,
+        book: OrderBook,
+        journal: Option<Journal>,
+    ) -> Result<(), EngineError> {
+        //{{{
+        let market = book.market().to_owned();
+        if self.routes.contains_key(&market) {
+            return Err(EngineError::MarketExist);
+        }
+
+        let slot = match self.mode {
+            EngineMode::Pinned => {
+                let worker = self.spawn_worker();
+                match self.workers.iter().position(|w| w.is_none()) {
+                    Some(slot) => {
+                        self.workers[slot] = Some(worker);
+                        slot
+                    }
+                    None => {
+                        self.workers.push(Some(worker));
+                        self.workers.len() - 1
+                    }
+                }
+            }
+            // the worker with the fewest orderbooks takes the new one
+            EngineMode::Pool(_) => self
+                .workers
+                .iter()
+                .enumerate()
+                .filter_map(|(slot, w)| w.as_ref().map(|w| (slot, w.market_num)))
+                .min_by_key(|(_, market_num)| *market_num)
+                .map(|(slot, _)| slot)
+                .ok_or(EngineError::EngineClosed)?,
+        };
+
+        let worker = self.workers[slot].as_mut().unwrap();
+        worker
+            .sender
+            .send(Command::AddMarket(book, journal))
+            .map_err(|_| EngineError::EngineClosed)?;
+        worker.market_num += 1;
+        self.routes.insert(market, slot);
+        Ok(())
+    } //}}}
+
+    /// remove the market from the engine and give back its orderbook.
+    /// the messages sent before are handled first
+    pub fn remove_market(&mut self, market: &str) -> Result<OrderBook, EngineError> {
+        //{{{
+        let slot = match self.routes.remove(market) {
+            Some(slot) => slot,
+            None => return Err(EngineError::MarketNotFound),
+        };
+
+        let (reply, recv) = unbounded();
+        let worker = self.workers[slot].as_mut().unwrap();
+        worker
+            .sender
+            .send(Command::RemoveMarket(market.to_owned(), reply))
+            .map_err(|_| EngineError::EngineClosed)?;
+        let book = recv.recv().map_err(|_| EngineError::EngineClosed)?;
+        worker.market_num -= 1;
+
+        if self.mode == EngineMode::Pinned {
+            // the pinned thread has nothing to do
+            let worker = self.workers[slot].take().unwrap();
+            let _ = worker.sender.send(Command::Closed);
+            let _ = worker.handle.join();
+        }
+        Ok(book)
+    } //}}}
+
+    /// route the message to the orderbook of the market
+    pub fn send(&self, market: &str, msg: Msg) -> Result<(), EngineError> {
+        //{{{
+        let slot = match self.routes.get(market) {
+            Some(slot) => *slot,
+            None => return Err(EngineError::MarketNotFound),
+        };
+        match &self.workers[slot] {
+            Some(worker) => worker
+                .sender
+                .send(Command::Msg(market.to_owned(), msg))
+                .map_err(|_| EngineError::EngineClosed),
+            None => Err(EngineError::EngineClosed),
+        }
+    } //}}}
+
+    /// the 24h rolling ticker of the market ending at now in milliseconds.
+    /// the messages sent before are counted
+    pub fn ticker(&self, market: &str, now: u64) -> Result<Ticker, EngineError> {
+        //{{{
+        let slot = match self.routes.get(market) {
+            Some(slot) => *slot,
+            None => return Err(EngineError::MarketNotFound),
+        };
+        let worker = match &self.workers[slot] {
+            Some(worker) => worker,
+            None => return Err(EngineError::EngineClosed),
+        };
+
+        let (reply, recv) = unbounded();
+        worker
+            .sender
+            .send(Command::Ticker(market.to_owned(), now, reply))
+            .map_err(|_| EngineError::EngineClosed)?;
+        recv.recv().map_err(|_| EngineError::EngineClosed)
+    } //}}}
+
+    pub fn markets(&self) -> Vec<String> {
+        self.routes.keys().cloned().collect()
+    }
+
+    /// stop all worker threads after the messages sent before are handled
+    pub fn shutdown(self) {
+        //{{{
+        for worker in self.workers.into_iter().flatten() {
+            let _ = worker.sender.send(Command::Closed);
+            let _ = worker.handle.join();
+        }
+    } //}}}
+}
+
+// journal a batch of messages and handle them in arrival order. the messages of every market
+// are appended and fsync'd as one journal batch, if it fails none of them is journaled and
+// they get the journal error
+fn run_batch(
+    markets: &mut HashMap<String, Market>,
+    batch: Vec<(String, Msg)>,
+    output: &Sender<EngineOutput>,
+) {
+    //{{{
+    let mut market_msgs: HashMap<&str, Vec<Msg>> = HashMap::new();
+    for (market, msg) in batch.iter() {
+        market_msgs.entry(market).or_default().push(*msg);
+    }
+    let mut market_seqs = HashMap::new();
+    for (name, msgs) in market_msgs {
+        let market = match markets.get_mut(name) {
+            Some(market) => market,
+            None => continue,
+        };
+        if let (Some(journal), false) = (&mut market.journal, market.halted) {
+            // the messages of one batch share the journal time
+            let time_stamp = market.book.clock.now();
+            match journal.append_batch(&msgs, time_stamp) {
+                Ok(seqs) => {
+                    market_seqs.insert(name.to_owned(), (seqs.into_iter(), time_stamp));
+                }
+                Err(_) => market.halted = true,
+            }
+        }
+    }
+
+    for (name, msg) in batch.into_iter() {
+        let market = match markets.get_mut(&name) {
+            Some(market) => market,
+            None => continue,
+        };
+        if market.halted {
+            let _ = output.send((name, Err(TradeError::JournalFailed)));
+            continue;
+        }
+
+        let journaled = market_seqs
+            .get_mut(&name)
+            .and_then(|(seqs, time_stamp)| seqs.next().map(|seq| (seq, *time_stamp)));
+        let result = match journaled {
+            Some((seq, time_stamp)) => market.book.apply_at(seq, time_stamp, msg),
+            None => market.book.handle(msg),
+        };
+        if let Some(result) = result {
+            if let Ok(result) = &result {
+                market.ticker.update_result(result);
+            }
+            let _ = output.send((name, result));
+        }
+    }
+
+    // reap the finished snapshot children
+    for (name, market) in markets.iter_mut() {
+        report_snapshot(name, &mut market.book, false, output);
+    }
+} //}}}
+
+// reap the finished snapshot child of the orderbook, or wait for it to finish,
+// and send its failure to the output channel
+fn report_snapshot(name: &str, book: &mut OrderBook, wait: bool, output: &Sender<EngineOutput>) {
+    //{{{
+    let snapshot = if wait {
+        book.wait_snapshot()
+    } else {
+        book.poll_snapshot()
+    };
+    if let Some(false) = snapshot.map(|snapshot| snapshot.success()) {
+        let _ = output.send((name.to_owned(), Err(TradeError::SnapshotFailed)));
+    }
+} //}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal;
+    use crate::SnapshotMode;
+    use order::proto::{OrderInfo, OrderSide};
+    use rust_decimal_macros::*;
+    use std::fs;
+
+    #[test]
+    fn match_engine_test() {
+        //{{{
+        for (i, mode) in [EngineMode::Pinned, EngineMode::Pool(2)].iter().enumerate() {
+            let path = std::env::temp_dir().join(format!(
+                "match_engine_test_{}_{}.j",
+                std::process::id(),
+                i
+            ));
+            let _ = fs::remove_file(&path);
+
+            let (output, results) = unbounded();
+            let mut engine = MatchEngine::new(*mode, output);
+            // only the BTC/USDT messages are journaled
+            let journal = journal::Journal::open(&path, 1).unwrap();
+            engine
+                .add_market(OrderBook::new(100, "BTC/USDT".to_owned()), Some(journal))
+                .unwrap();
+            for market in ["ETH/USDT", "ETH/BTC"].iter() {
+                engine
+                    .add_market(OrderBook::new(100, market.to_string()), None)
+                    .unwrap();
+            }
+            assert_eq!(
+                engine.add_market(OrderBook::new(100, "ETH/BTC".to_owned()), None),
+                Err(EngineError::MarketExist)
+            );
+            assert_eq!(engine.markets().len(), 3);
+
+            let order = OrderInfo::new(
+                1,
+                10001,
+                OrderSide::Bid,
+                dec!(100),
+                dec!(1.2),
+                (dec!(0), dec!(0)),
+            );
+            engine.send("BTC/USDT", Msg::SimpleOrder(order)).unwrap();
+            engine.send("ETH/USDT", Msg::SimpleOrder(order)).unwrap();
+
+            let mut markets = Vec::new();
+            for _ in 0..2 {
+                let (market, result) = results.recv().unwrap();
+                assert!(result.unwrap().rested.is_some());
+                markets.push(market);
+            }
+            markets.sort();
+            assert_eq!(markets, vec!["BTC/USDT", "ETH/USDT"]);
+
+            // the maker is not traded yet
+            let ticker = engine.ticker("BTC/USDT", 0).unwrap();
+            assert_eq!(ticker.best_bid, dec!(1.2));
+            assert_eq!(ticker.trade_count, 0);
+            assert_eq!(
+                engine.ticker("BTC/BTC", 0),
+                Err(EngineError::MarketNotFound)
+            );
+
+            // the messages of one market are handled in order
+            engine
+                .send("BTC/USDT", Msg::CancelOrder((1, 10001)))
+                .unwrap();
+            let (market, result) = results.recv().unwrap();
+            assert_eq!(market, "BTC/USDT");
+            assert_eq!(result.unwrap().trades.len(), 1);
+
+            let book = engine.remove_market("ETH/USDT").unwrap();
+            assert_eq!(book.order_index.len(), 1);
+            assert_eq!(
+                engine.send("ETH/USDT", Msg::SimpleOrder(order)),
+                Err(EngineError::MarketNotFound)
+            );
+            assert_eq!(engine.markets().len(), 2);
+
+            // the removed market can be added again
+            engine.add_market(book, None).unwrap();
+            assert_eq!(engine.markets().len(), 3);
+
+            // the failed snapshot child is reported before the orderbook is given back
+            let mut book = OrderBook::new(100, "ETH/ETH".to_owned());
+            let dir = path.with_extension("missing");
+            book.set_snapshot_mode(SnapshotMode::Fork, dir.to_str().unwrap());
+            engine.add_market(book, None).unwrap();
+            engine.send("ETH/ETH", Msg::Snapshot).unwrap();
+            engine.remove_market("ETH/ETH").unwrap();
+            let (market, result) = results.recv().unwrap();
+            assert_eq!(market, "ETH/ETH");
+            assert_eq!(result, Err(TradeError::SnapshotFailed));
+
+            // the journal replay rebuilds the same orderbook
+            let book = engine.remove_market("BTC/USDT").unwrap();
+            assert_eq!(book.seq(), 2);
+            let mut replayed = OrderBook::new(100, "BTC/USDT".to_owned());
+            let mut replayed_num = 0;
+            journal::replay(&mut replayed, &path, |_| replayed_num += 1).unwrap();
+            assert_eq!(replayed_num, 2);
+            assert_eq!(
+                serde_json::to_value(&replayed).unwrap(),
+                serde_json::to_value(&book).unwrap()
+            );
+            engine.shutdown();
+            let _ = fs::remove_file(&path);
+        }
+    } //}}}
+}
@@ -0,0 +1,381 @@
+use crate::{MatchResult, Msg, OrderBook};
+use common::crc32::crc32;
+use order::proto::TradeError;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*, BufReader, SeekFrom};
+use std::path::Path;
+
+const HEADER_LEN: usize = 8; // payload len (u32 le) + payload crc32 (u32 le)
+const MAX_ENTRY_LEN: usize = 1 << 20; // the longer payload len is corruption
+
+/// One journaled message
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub seq: u64, // monotonically increasing sequence number, starts from 1
+    #[serde(default)]
+    pub time_stamp: u64, // time of the message in milliseconds, the trade records carry it
+    pub msg: Msg,
+}
+
+/// Append-only journal of the inbound messages of one orderbook.
+/// Every entry is a length-prefixed and checksummed json payload.
+pub struct Journal {
+    file: File,
+    len: u64,          // length of the appended entries
+    last_seq: u64,     // sequence number of the last appended message
+    unsynced: usize,   // messages appended since the last fsync
+    batch_size: usize, // fsync after appending this number of messages
+    #[cfg(test)]
+    fail_seq: u64, // the append of this sequence number fails
+}
+
+impl Journal {
+    /// open or create the journal file. the torn entry at the tail left by a crash is dropped,
+    /// the broken entry before it is an error
+    pub fn open<P: AsRef<Path>>(path: P, batch_size: usize) -> io::Result<Journal> {
+        //{{{
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut reader = JournalReader::new(file.try_clone()?, 0)?;
+        let mut last_seq = 0;
+        for entry in &mut reader {
+            last_seq = entry?.seq;
+        }
+        let len = reader.valid_len();
+        if len < file.metadata()?.len() {
+            file.set_len(len)?;
+            file.sync_data()?;
+        }
+
+        Ok(Journal {
+            file: file,
+            len: len,
+            last_seq: last_seq,
+            unsynced: 0,
+            batch_size: batch_size.max(1),
+            #[cfg(test)]
+            fail_seq: 0,
+        })
+    } //}}}
+
+    /// append the message with its time and return its sequence number.
+    /// the message is durable only after the next fsync
+    pub fn append(&mut self, msg: &Msg, time_stamp: u64) -> io::Result<u64> {
+        //{{{
+        let entry = JournalEntry {
+            seq: self.last_seq + 1,
+            time_stamp: time_stamp,
+            msg: *msg,
+        };
+        #[cfg(test)]
+        {
+            if entry.seq == self.fail_seq {
+                return Err(io::Error::from_raw_os_error(libc::ENOSPC));
+            }
+        }
+        let payload = serde_json::to_vec(&entry)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if payload.len() > MAX_ENTRY_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "journal entry is too long",
+            ));
+        }
+
+        let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&crc32(&payload).to_le_bytes());
+        buf.extend_from_slice(&payload);
+        self.file.write_all(&buf)?;
+
+        self.len += buf.len() as u64;
+        self.last_seq = entry.seq;
+        self.unsynced += 1;
+        if self.unsynced >= self.batch_size {
+            self.sync()?;
+        }
+        Ok(entry.seq)
+    } //}}}
+
+    /// append the messages with their time and fsync them as one batch, return their sequence
+    /// numbers. if any of them fails, the messages already written are truncated, so no message
+    /// of the failed batch is replayed later. the journal can not be used any more if the
+    /// truncation fails as well
+    pub fn append_batch(&mut self, msgs: &[Msg], time_stamp: u64) -> io::Result<Vec<u64>> {
+        //{{{
+        let (len, last_seq) = (self.len, self.last_seq);
+        let result = msgs
+            .iter()
+            .map(|msg| self.append(msg, time_stamp))
+            .collect::<io::Result<Vec<u64>>>()
+            .and_then(|seqs| self.sync().map(|_| seqs));
+        if result.is_err() {
+            self.file.set_len(len)?;
+            self.file.sync_data()?;
+            self.len = len;
+            self.last_seq = last_seq;
+            self.unsynced = 0;
+        }
+        result
+    } //}}}
+
+    /// fsync the appended messages
+    pub fn sync(&mut self) -> io::Result<()> {
+        //{{{
+        if self.unsynced == 0 {
+            return Ok(());
+        }
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    } //}}}
+
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// read the entries after the sequence number one by one
+    pub fn read<P: AsRef<Path>>(path: P, after_seq: u64) -> io::Result<JournalReader> {
+        JournalReader::new(File::open(path)?, after_seq)
+    }
+}
+
+/// Reader of the journal entries from the head of the file. only the last entry can be torn
+/// by a crash, the reader stops there. the complete entry with the bad crc or the entry
+/// longer than the max len is corruption, the reader returns the error and stops
+pub struct JournalReader {
+    reader: BufReader<File>,
+    after_seq: u64, // the entries up to this sequence number are skipped
+    valid_len: u64, // length of the entries read
+    done: bool,
+}
+
+impl JournalReader {
+    fn new(mut file: File, after_seq: u64) -> io::Result<JournalReader> {
+        //{{{
+        file.seek(SeekFrom::Start(0))?;
+        Ok(JournalReader {
+            reader: BufReader::new(file),
+            after_seq: after_seq,
+            valid_len: 0,
+            done: false,
+        })
+    } //}}}
+
+    /// length of the entries read, the torn tail is not counted
+    pub fn valid_len(&self) -> u64 {
+        self.valid_len
+    }
+
+    // read the next entry, None at the end of file or the torn entry
+    fn read_entry(&mut self) -> io::Result<Option<JournalEntry>> {
+        //{{{
+        let mut header = [0u8; HEADER_LEN];
+        if read_full(&mut self.reader, &mut header)? < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        if len > MAX_ENTRY_LEN {
+            return Err(corrupt(self.valid_len, "entry is too long"));
+        }
+
+        let mut payload = vec![0u8; len];
+        if read_full(&mut self.reader, &mut payload)? < len {
+            return Ok(None);
+        }
+        if crc32(&payload) != crc {
+            return Err(corrupt(self.valid_len, "entry crc mismatch"));
+        }
+        let entry = serde_json::from_slice::<JournalEntry>(&payload)
+            .map_err(|_| corrupt(self.valid_len, "entry is broken"))?;
+        self.valid_len += (HEADER_LEN + len) as u64;
+        Ok(Some(entry))
+    } //}}}
+}
+
+impl Iterator for JournalReader {
+    type Item = io::Result<JournalEntry>;
+
+    fn next(&mut self) -> Option<io::Result<JournalEntry>> {
+        //{{{
+        while !self.done {
+            match self.read_entry() {
+                Ok(Some(entry)) if entry.seq <= self.after_seq => continue,
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    } //}}}
+}
+
+fn corrupt(offset: u64, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("journal is corrupt at {}: {}", offset, msg),
+    )
+}
+
+// read until the buffer is full or the end of file, return the read length
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    //{{{
+    let mut read_len = 0;
+    while read_len < buf.len() {
+        match reader.read(&mut buf[read_len..]) {
+            Ok(0) => break,
+            Ok(n) => read_len += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read_len)
+} //}}}
+
+/// feed the journal entries after the sequence number of the orderbook back through the
+/// orderbook one by one, the results are passed to the callback in the journal order.
+/// the snapshot message is skipped
+pub fn replay<P, F>(orderbook: &mut OrderBook, path: P, mut on_result: F) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(Result<MatchResult, TradeError>),
+{
+    //{{{
+    for entry in Journal::read(path, orderbook.seq())? {
+        let entry = entry?;
+        if entry.msg == Msg::Snapshot {
+            orderbook.seq = entry.seq;
+            continue;
+        }
+        if let Some(result) = orderbook.apply_at(entry.seq, entry.time_stamp, entry.msg) {
+            on_result(result);
+        }
+    }
+    Ok(())
+} //}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Signal;
+    use crossbeam_channel::unbounded;
+    use order::proto::{OrderInfo, OrderSide};
+    use rust_decimal_macros::*;
+    use std::fs;
+
+    #[test]
+    fn journal_replay_test() {
+        //{{{
+        let path = std::env::temp_dir().join(format!("journal_test_{}.j", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (sender, recv) = unbounded();
+        let (signal_sender, signal) = unbounded();
+        let (output, results) = unbounded();
+        let journal = Journal::open(&path, 2).unwrap();
+        let handle =
+            OrderBook::new(100, "BTC_USDT".to_owned()).run(recv, signal, output, Some(journal));
+
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        sender.send(Msg::SimpleOrder(order)).unwrap();
+        order.id = 2;
+        order.price = dec!(1.21);
+        sender.send(Msg::SimpleOrder(order)).unwrap();
+        order.id = 3;
+        order.uid = 10002;
+        order.side = OrderSide::Ask;
+        order.raw_qty = dec!(150);
+        order.remain_qty = dec!(150);
+        sender.send(Msg::SimpleOrder(order)).unwrap();
+        sender.send(Msg::CancelOrder((1, 10001))).unwrap();
+        sender.send(Msg::Snapshot).unwrap();
+
+        let mut expected = Vec::new();
+        for _ in 0..4 {
+            expected.push(results.recv().unwrap());
+        }
+        signal_sender.send(Signal::Closed).unwrap();
+        let orderbook = handle.join().unwrap();
+        assert_eq!(orderbook.seq(), 5);
+
+        // the torn entry at the tail is dropped
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[100, 0, 0, 0, 1, 2]).unwrap();
+        let journal = Journal::open(&path, 2).unwrap();
+        assert_eq!(journal.last_seq(), 5);
+        assert_eq!(Journal::read(&path, 3).unwrap().count(), 2);
+
+        // the replay reproduces the same results and orderbook
+        let mut replayed = OrderBook::new(100, "BTC_USDT".to_owned());
+        let mut replayed_results = Vec::new();
+        replay(&mut replayed, &path, |result| replayed_results.push(result)).unwrap();
+        assert_eq!(replayed_results, expected);
+        assert_eq!(replayed.seq(), 5);
+        assert_eq!(
+            serde_json::to_value(&replayed).unwrap(),
+            serde_json::to_value(&orderbook).unwrap()
+        );
+        let mut replayed_num = 0;
+        replay(&mut replayed, &path, |_| replayed_num += 1).unwrap();
+        assert_eq!(replayed_num, 0);
+
+        // the failed batch is truncated, none of its messages is replayed
+        let mut journal = Journal::open(&path, 1).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        journal.fail_seq = 7;
+        order.id = 4;
+        let batch = [Msg::SimpleOrder(order), Msg::CancelOrder((2, 10001))];
+        assert!(journal.append_batch(&batch, 0).is_err());
+        assert_eq!(journal.last_seq(), 5);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        replay(&mut replayed, &path, |_| replayed_num += 1).unwrap();
+        assert_eq!(replayed_num, 0);
+        journal.fail_seq = 0;
+        assert_eq!(journal.append_batch(&batch, 0).unwrap(), vec![6, 7]);
+        drop(journal);
+
+        // the broken entry in the middle is not truncated
+        let mut data = fs::read(&path).unwrap();
+        data[HEADER_LEN + 1] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+        assert!(Journal::open(&path, 2).is_err());
+        assert!(Journal::read(&path, 0).unwrap().any(|entry| entry.is_err()));
+        assert_eq!(fs::metadata(&path).unwrap().len(), data.len() as u64);
+
+        // the complete entry with the bad crc is corruption even at the tail
+        data[HEADER_LEN + 1] ^= 0xff;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+        assert!(Journal::open(&path, 2).is_err());
+
+        // the entry longer than the max len is corruption even at the tail
+        data[last] ^= 0xff;
+        data.extend_from_slice(&(MAX_ENTRY_LEN as u32 + 1).to_le_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        fs::write(&path, &data).unwrap();
+        assert!(Journal::open(&path, 2).is_err());
+
+        let _ = fs::remove_file(&path);
+    } //}}}
+}
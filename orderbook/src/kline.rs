@@ -0,0 +1,298 @@
+use crate::journal;
+use crate::{MatchResult, OrderBook};
+use order::proto::{TradeRecord, TradeType};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// The candle interval, the candles are aligned to the unix epoch
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, SmartDefault, Serialize, Deserialize)]
+pub enum KlineInterval {
+    #[default]
+    Min1, // 1 minute
+    Min5,  // 5 minutes
+    Hour1, // 1 hour
+    Day1,  // 1 day
+}
+
+impl KlineInterval {
+    pub const ALL: [KlineInterval; 4] = [
+        KlineInterval::Min1,
+        KlineInterval::Min5,
+        KlineInterval::Hour1,
+        KlineInterval::Day1,
+    ];
+
+    /// the interval length in milliseconds
+    pub fn millis(&self) -> u64 {
+        match self {
+            KlineInterval::Min1 => 60 * 1000,
+            KlineInterval::Min5 => 5 * 60 * 1000,
+            KlineInterval::Hour1 => 60 * 60 * 1000,
+            KlineInterval::Day1 => 24 * 60 * 60 * 1000,
+        }
+    }
+
+    // the open time of the candle which contains the time stamp
+    fn open_time(&self, time_stamp: u64) -> u64 {
+        time_stamp - time_stamp % self.millis()
+    }
+}
+
+/// One candle of the executed trades
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Kline {
+    pub interval: KlineInterval, // candle interval
+    pub open_time: u64,          // start of the candle in milliseconds, inclusive
+    pub close_time: u64,         // end of the candle in milliseconds, exclusive
+    pub open: Decimal,           // first trade price
+    pub high: Decimal,           // highest trade price
+    pub low: Decimal,            // lowest trade price
+    pub close: Decimal,          // last trade price
+    pub volume: Decimal,         // traded base qty
+    pub quote_volume: Decimal,   // traded quote qty
+    pub trade_count: u64,        // number of trades
+}
+
+impl Kline {
+    pub(crate) fn new(interval: KlineInterval, record: &TradeRecord) -> Kline {
+        //{{{
+        let open_time = interval.open_time(record.time_stamp);
+        Kline {
+            interval: interval,
+            open_time: open_time,
+            close_time: open_time + interval.millis(),
+            open: record.trade_price,
+            high: record.trade_price,
+            low: record.trade_price,
+            close: record.trade_price,
+            volume: record.trade_qty,
+            quote_volume: record.trade_oppo_qty,
+            trade_count: 1,
+        }
+    } //}}}
+
+    pub(crate) fn update(&mut self, record: &TradeRecord) {
+        //{{{
+        if record.trade_price > self.high {
+            self.high = record.trade_price;
+        }
+        if record.trade_price < self.low {
+            self.low = record.trade_price;
+        }
+        self.close = record.trade_price;
+        self.volume += record.trade_qty;
+        self.quote_volume += record.trade_oppo_qty;
+        self.trade_count += 1;
+    } //}}}
+}
+
+/// Aggregates the trade records of one market into the candles of every interval.
+/// Only the open candles are kept, the closed ones are handed back to the caller.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KlineAggregator {
+    market: String,
+    intervals: Vec<KlineInterval>, // aggregated intervals
+    current: Vec<Option<Kline>>,   // the open candle of every interval, None before any trade
+}
+
+impl KlineAggregator {
+    pub fn new(market: String, intervals: &[KlineInterval]) -> KlineAggregator {
+        //{{{
+        KlineAggregator {
+            market: market,
+            intervals: intervals.to_vec(),
+            current: vec![None; intervals.len()],
+        }
+    } //}}}
+
+    pub fn market(&self) -> &str {
+        &self.market
+    }
+
+    /// the open candle of the interval
+    pub fn current(&self, interval: KlineInterval) -> Option<Kline> {
+        //{{{
+        self.intervals
+            .iter()
+            .position(|i| *i == interval)
+            .and_then(|i| self.current[i])
+    } //}}}
+
+    /// feed the trade record and return the candles closed by it.
+    /// the cancel and reject records are ignored. the interval without trades has no candle,
+    /// and the record older than the open candle is counted into the open candle
+    pub fn update(&mut self, record: &TradeRecord) -> Vec<Kline> {
+        //{{{
+        let mut closed = Vec::new();
+        if record.trade_type != TradeType::SimpleTrade {
+            return closed;
+        }
+
+        for (interval, current) in self.intervals.iter().zip(self.current.iter_mut()) {
+            match current {
+                Some(kline) if record.time_stamp < kline.close_time => kline.update(record),
+                _ => {
+                    if let Some(kline) = current.take() {
+                        closed.push(kline);
+                    }
+                    *current = Some(Kline::new(*interval, record));
+                }
+            }
+        }
+        closed
+    } //}}}
+
+    /// feed the trade records of the match result and its triggered stop orders
+    pub fn update_result(&mut self, result: &MatchResult) -> Vec<Kline> {
+        //{{{
+        let mut closed = Vec::new();
+        for record in result.records() {
+            closed.extend(self.update(record));
+        }
+        closed
+    } //}}}
+
+    /// rebuild the candles by replaying the journal through the orderbook,
+    /// return the closed candles in close order
+    pub fn replay<P: AsRef<Path>>(
+        &mut self,
+        orderbook: &mut OrderBook,
+        path: P,
+    ) -> io::Result<Vec<Kline>> {
+        //{{{
+        let mut closed = Vec::new();
+        journal::replay(orderbook, path, |result| {
+            if let Ok(result) = result {
+                closed.extend(self.update_result(&result));
+            }
+        })?;
+        Ok(closed)
+    } //}}}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::journal::Journal;
+    use crate::Msg;
+    use order::proto::{OrderInfo, OrderSide};
+    use rust_decimal_macros::*;
+    use std::fs;
+
+    // the trade record at the time stamp, shared by the kline and ticker tests
+    pub(crate) fn record(time_stamp: u64, price: Decimal, qty: Decimal) -> TradeRecord {
+        TradeRecord {
+            trade_price: price,
+            trade_qty: qty,
+            trade_oppo_qty: price * qty,
+            time_stamp: time_stamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn kline_test() {
+        //{{{
+        let mut aggregator = KlineAggregator::new(
+            "BTC/USDT".to_owned(),
+            &[KlineInterval::Min1, KlineInterval::Min5],
+        );
+        assert!(aggregator
+            .update(&record(1000, dec!(10), dec!(1)))
+            .is_empty());
+        assert!(aggregator
+            .update(&record(2000, dec!(12), dec!(2)))
+            .is_empty());
+        assert!(aggregator
+            .update(&record(3000, dec!(9), dec!(1)))
+            .is_empty());
+
+        let mut cancel = record(4000, dec!(100), dec!(100));
+        cancel.trade_type = TradeType::CancelTrade;
+        assert!(aggregator.update(&cancel).is_empty());
+
+        // the next minute closes the 1m candle only
+        let closed = aggregator.update(&record(61000, dec!(11), dec!(1)));
+        assert_eq!(
+            closed,
+            vec![Kline {
+                interval: KlineInterval::Min1,
+                open_time: 0,
+                close_time: 60000,
+                open: dec!(10),
+                high: dec!(12),
+                low: dec!(9),
+                close: dec!(9),
+                volume: dec!(4),
+                quote_volume: dec!(43),
+                trade_count: 3,
+            }]
+        );
+        let kline = aggregator.current(KlineInterval::Min5).unwrap();
+        assert_eq!(kline.trade_count, 4);
+        assert_eq!(kline.close, dec!(11));
+        assert!(aggregator.current(KlineInterval::Hour1).is_none());
+
+        // the empty intervals are skipped
+        let closed = aggregator.update(&record(3600000, dec!(8), dec!(1)));
+        assert_eq!(closed.len(), 2);
+        assert_eq!(closed[0].open_time, 60000);
+        assert_eq!(closed[1].open_time, 0);
+        assert_eq!(
+            aggregator.current(KlineInterval::Min5).unwrap().open_time,
+            3600000
+        );
+    } //}}}
+
+    #[test]
+    fn kline_replay_test() {
+        //{{{
+        let path = std::env::temp_dir().join(format!("kline_test_{}.j", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut journal = Journal::open(&path, 1).unwrap();
+        let mut aggregator = KlineAggregator::new("BTC/USDT".to_owned(), &KlineInterval::ALL);
+
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        let mut msgs = vec![Msg::SimpleOrder(order)];
+        order.id = 2;
+        order.uid = 10002;
+        order.side = OrderSide::Ask;
+        order.raw_qty = dec!(30);
+        order.remain_qty = dec!(30);
+        msgs.push(Msg::SimpleOrder(order));
+        order.id = 3;
+        msgs.push(Msg::SimpleOrder(order));
+        for (i, msg) in msgs.into_iter().enumerate() {
+            // the trades fall into different minutes
+            let time_stamp = i as u64 * 60 * 1000;
+            let seq = journal.append(&msg, time_stamp).unwrap();
+            if let Some(Ok(result)) = orderbook.apply_at(seq, time_stamp, msg) {
+                aggregator.update_result(&result);
+            }
+        }
+
+        let mut rebuilt = KlineAggregator::new("BTC/USDT".to_owned(), &KlineInterval::ALL);
+        let mut replayed = OrderBook::new(100, "BTC/USDT".to_owned());
+        let closed = rebuilt.replay(&mut replayed, &path).unwrap();
+        assert_eq!(rebuilt, aggregator);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].open_time, 60 * 1000);
+        let kline = rebuilt.current(KlineInterval::Day1).unwrap();
+        assert_eq!(kline.trade_count, 2);
+        assert_eq!(kline.volume, dec!(60));
+        assert_eq!(kline.quote_volume, dec!(72));
+
+        let _ = fs::remove_file(&path);
+    } //}}}
+}
@@ -0,0 +1,3639 @@
+#![feature(map_first_last)]
+#![allow(stable_features)]
+#![allow(
+    clippy::redundant_field_names,
+    clippy::field_reassign_with_default,
+    clippy::needless_return,
+    clippy::large_enum_variant,
+    clippy::bool_assert_comparison,
+    clippy::single_match
+)]
+use chrono::offset::LocalResult;
+use chrono::prelude::*;
+use clock::Clock;
+use common::bitmap::BitMap;
+use crossbeam_channel::{select, Receiver, Sender};
+use journal::Journal;
+use order::proto::{
+    OrderInfo, OrderOp, OrderSide, OrderStatus, QtyType, TimeInForce, TradeError, TradeRecord,
+};
+use rust_decimal::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use serde::{Deserialize, Serialize};
+use snapshot::SnapshotError;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, CString};
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufWriter};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+#[macro_use]
+extern crate smart_default;
+
+pub mod clock;
+pub mod engine;
+pub mod journal;
+pub mod kline;
+pub mod snapshot;
+pub mod ticker;
+
+pub const SNAPSHOT_DIR: &str = "batch"; // the snapshot files are written here
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+struct PriceNode {
+    qty: Decimal,      // curr node order qty
+    price: Decimal,    // curr node price
+    order_slot: usize, // curr node order number
+    last_slot: usize,  // last order slot
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
+pub enum Signal {
+    #[default]
+    Closed,
+    CancelAllOrder,
+}
+
+/// How the snapshot is written
+#[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
+pub enum SnapshotMode {
+    #[default]
+    Blocking, // serialize the orderbook on the matching thread
+    Fork, // fork the process, the child serializes the copy-on-write orderbook
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct SnapshotChild {
+    pid: libc::pid_t, // pid of the child process
+    seq: u64,         // sequence number at the fork
+}
+
+/// The finished snapshot child process
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SnapshotResult {
+    pub pid: i32,               // pid of the child process
+    pub seq: u64,               // sequence number of the snapshot
+    pub exit_code: Option<i32>, // exit code of the child, None if it is killed by signal
+}
+
+impl SnapshotResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// How to handle a post only order which would cross the opposite leader
+#[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
+pub enum PostOnlyMode {
+    #[default]
+    Reject, // reject the whole order
+    Reprice, // reprice the order one tick away from the opposite leader
+}
+
+/// How to handle a taker order which would trade with a maker order of the same uid
+#[derive(Copy, Clone, Debug, PartialEq, SmartDefault, Serialize, Deserialize)]
+pub enum SelfTradePrevention {
+    #[default]
+    None, // allow self trade
+    CancelNewest,       // cancel the taker remain part
+    CancelOldest,       // cancel the maker order and continue matching
+    CancelBoth,         // cancel both the taker remain part and the maker order
+    DecrementAndCancel, // decrement both by the smaller qty, cancel the smaller one
+}
+
+/// The trading rules of the market, zero value means no limit
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MarketConfig {
+    pub base_asset: String,    // the traded asset, qty is counted in it
+    pub quote_asset: String,   // the pricing asset, price and funds are counted in it
+    pub price_tick: Decimal,   // min price movement
+    pub max_price: Decimal,    // max order price
+    pub qty_step: Decimal,     // min qty movement
+    pub min_qty: Decimal,      // min order qty
+    pub max_qty: Decimal,      // max order qty
+    pub min_notional: Decimal, // min order value, price * qty
+    pub price_scale: u32,      // max decimal places of price
+    pub qty_scale: u32,        // max decimal places of qty
+}
+
+impl MarketConfig {
+    // check whether the price is on the price tick and the price scale
+    fn price_legal(&self, price: Decimal) -> bool {
+        //{{{
+        if price.is_zero() || !price.is_sign_positive() {
+            return false;
+        }
+        if self.price_scale != 0 && price.normalize().scale() > self.price_scale {
+            return false;
+        }
+        if !self.max_price.is_zero() && price > self.max_price {
+            return false;
+        }
+        self.price_tick.is_zero() || (price % self.price_tick).is_zero()
+    } //}}}
+
+    // check whether the qty is on the qty step, the qty scale and in the qty range
+    fn qty_legal(&self, qty: Decimal) -> bool {
+        //{{{
+        self.lot_legal(qty) && (self.max_qty.is_zero() || qty <= self.max_qty)
+    } //}}}
+
+    // check whether the display qty of iceberg order is a legal lot, zero means not iceberg
+    fn display_legal(&self, display_qty: Decimal) -> bool {
+        display_qty.is_zero() || self.lot_legal(display_qty)
+    }
+
+    // check whether the qty is on the qty step, the qty scale and not less than the min qty
+    fn lot_legal(&self, qty: Decimal) -> bool {
+        //{{{
+        if qty.is_zero() || !qty.is_sign_positive() {
+            return false;
+        }
+        if self.qty_scale != 0 && qty.normalize().scale() > self.qty_scale {
+            return false;
+        }
+        if !self.qty_step.is_zero() && !(qty % self.qty_step).is_zero() {
+            return false;
+        }
+        qty >= self.min_qty
+    } //}}}
+
+    // check whether the order value is in the decimal range and reaches the min notional
+    fn notional_legal(&self, price: Decimal, qty: Decimal) -> bool {
+        match price.checked_mul(qty) {
+            Some(notional) => self.min_notional.is_zero() || notional >= self.min_notional,
+            None => false,
+        }
+    }
+
+    // the min tradable qty movement from the qty step or the qty scale, zero means no limit
+    fn lot_size(&self) -> Decimal {
+        //{{{
+        if !self.qty_step.is_zero() {
+            self.qty_step
+        } else if self.qty_scale != 0 {
+            Decimal::new(1, self.qty_scale)
+        } else {
+            dec!(0)
+        }
+    } //}}}
+
+    // the visible slice of iceberg order with the qty left, the reserve which would be
+    // less than the min qty is shown together with the slice
+    fn iceberg_slice(&self, display_qty: Decimal, qty: Decimal) -> Decimal {
+        //{{{
+        if display_qty.is_zero() || qty <= display_qty || qty - display_qty < self.min_qty {
+            qty
+        } else {
+            display_qty
+        }
+    } //}}}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OrderBook {
+    market: String,        // curr orderbook market ID
+    bid_leader: PriceNode, // the best buy price_node
+    ask_leader: PriceNode, // the best sell price_node
+
+    orders: Vec<OrderInfo>, // store order array
+    order_bitmap: BitMap,   // bitmap index of order
+
+    bid_price_index: BTreeMap<Decimal, PriceNode>, // price_node of buy skiplist index
+    ask_price_index: BTreeMap<Decimal, PriceNode>, // price_node of sell skiplist index
+    order_index: HashMap<u64, usize>,              // order id -> order slot
+
+    config: MarketConfig,         // trading rules of the market
+    post_only_mode: PostOnlyMode, // how to handle crossed post only order
+    order_events_enabled: bool,   // record the l3 events, off by default
+
+    stp_mode: SelfTradePrevention, // self trade prevention mode
+    seq: u64,                      // journal sequence number of the last handled message
+    trade_seq: u64,                // id of the last trade record
+    #[serde(skip)]
+    time_stamp: u64, // time of the message being handled, in milliseconds
+    #[serde(skip, default = "clock::default_clock")]
+    clock: Box<dyn Clock>, // stamps the messages without journal time
+
+    snapshot_mode: SnapshotMode, // how to write the snapshot
+    snapshot_dir: String,        // the snapshot files are written here
+    #[serde(skip)]
+    snapshot_child: Option<SnapshotChild>, // the child process writing the snapshot
+    #[serde(skip)]
+    last_snapshot: Option<SnapshotResult>, // the result of the last finished snapshot child
+    #[serde(skip)]
+    depth_touched: BTreeMap<(u8, Decimal), Decimal>, // (side, price) -> level qty before change
+    #[serde(skip)]
+    order_events: Vec<OrderEvent>, // l3 events not taken yet
+
+    last_price: Decimal,                                  // the last trade price
+    stop_order_index: HashMap<u64, (OrderSide, Decimal)>, // stop order id -> (side, trigger price)
+    bid_stop_index: BTreeMap<Decimal, Vec<OrderInfo>>,    // buy stop orders by trigger price
+    ask_stop_index: BTreeMap<Decimal, Vec<OrderInfo>>,    // sell stop orders by trigger price
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Msg {
+    SimpleOrder(OrderInfo),  // new order
+    CancelOrder((u64, u64)), // cancel order operation, (order id, uid)
+    CancelAllOrder,          // cancel all order
+    MassCancel(MassCancel),  // cancel the orders chosen by the filter
+    Snapshot,                // start snapshot signal
+}
+
+/// Which orders a mass cancel removes, including the stop orders waiting for trigger
+/// except for the price band filter
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MassCancel {
+    All,                                    // all orders
+    User(u64),                              // all orders of the uid
+    Side(OrderSide),                        // all bids or all asks
+    PriceBand(OrderSide, Decimal, Decimal), // orders of the side priced in [low, high]
+}
+
+impl MassCancel {
+    // check whether the order is chosen by the filter
+    fn matches(&self, order: &OrderInfo, stop: bool) -> bool {
+        match *self {
+            MassCancel::All => true,
+            MassCancel::User(uid) => order.uid == uid,
+            MassCancel::Side(side) => order.side == side,
+            MassCancel::PriceBand(side, low, high) => {
+                !stop && order.side == side && order.price >= low && order.price <= high
+            }
+        }
+    }
+}
+
+/// The result of one order passing through `OrderBook::match_entry`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MatchResult {
+    pub order_id: u64,                   // taker order id
+    pub trades: Vec<TradeRecord>,        // trade records in produced order
+    pub taker_status: OrderStatus,       // taker order status after matching
+    pub filled_makers: Vec<OrderInfo>,   // maker orders filled and removed from the orderbook
+    pub rested: Option<OrderInfo>,       // taker remain part inserted into the orderbook
+    pub rejected_qty: Decimal,           // taker remain part neither traded nor rested
+    pub triggered: Vec<MatchResult>,     // stop orders triggered by this order, in trigger order
+    pub depth_updates: Vec<DepthUpdate>, // price levels changed by this order and the triggered
+    pub order_events: Vec<OrderEvent>,   // l3 events of the resting orders, in produced order
+}
+
+impl MatchResult {
+    /// the trade records of the order and of the stop orders triggered by it, in produced order
+    pub fn records(&self) -> Box<dyn Iterator<Item = &TradeRecord> + '_> {
+        Box::new(
+            self.trades
+                .iter()
+                .chain(self.triggered.iter().flat_map(|result| result.records())),
+        )
+    }
+}
+
+/// One aggregated price level
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceLevel {
+    pub price: Decimal, // level price
+    pub qty: Decimal,   // total visible qty of the orders at the price
+}
+
+/// The top price levels of both sides
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Depth {
+    pub seq: u64,              // sequence number of the orderbook
+    pub bids: Vec<PriceLevel>, // from the highest price
+    pub asks: Vec<PriceLevel>, // from the lowest price
+}
+
+/// The change of one resting order in the l3 feed
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OrderEventKind {
+    Add,     // the order is queued at the tail of the price level
+    Reduce,  // the visible qty of the order is reduced in place
+    Execute, // the order trades as maker, it leaves the price level if nothing remains
+    Delete,  // the order leaves the price level without trading
+}
+
+/// One l3 event of the resting order
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrderEvent {
+    pub seq: u64,             // sequence number of the orderbook
+    pub kind: OrderEventKind, // event kind
+    pub order_id: u64,        // order id
+    pub side: OrderSide,      // order side
+    pub price: Decimal,       // price level of the order
+    pub qty: Decimal,         // added, reduced, executed or deleted qty
+    pub remain_qty: Decimal,  // visible qty of the order after the event
+    pub position: usize,      // queue position in the price level before removal, 0 is the head
+}
+
+/// The new qty of the changed price level
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DepthUpdate {
+    pub seq: u64,        // sequence number of the orderbook
+    pub side: OrderSide, // level side
+    pub price: Decimal,  // level price
+    pub qty: Decimal,    // new level qty, 0 if the level is removed
+}
+
+impl OrderBook {
+    pub fn new(max_order_num: usize, market: String) -> OrderBook {
+        //{{{
+        let orders: Vec<OrderInfo> = vec![OrderInfo::default(); max_order_num];
+        OrderBook {
+            market: market,
+            bid_leader: Default::default(),
+            ask_leader: Default::default(),
+            orders: orders,
+            order_bitmap: BitMap::new(max_order_num),
+            bid_price_index: BTreeMap::new(),
+            ask_price_index: BTreeMap::new(),
+            order_index: HashMap::with_capacity(max_order_num),
+            config: MarketConfig::default(),
+            post_only_mode: PostOnlyMode::Reject,
+            stp_mode: SelfTradePrevention::None,
+            seq: 0,
+            snapshot_mode: SnapshotMode::Blocking,
+            snapshot_dir: SNAPSHOT_DIR.to_owned(),
+            snapshot_child: None,
+            last_snapshot: None,
+            trade_seq: 0,
+            time_stamp: 0,
+            clock: clock::default_clock(),
+            depth_touched: BTreeMap::new(),
+            order_events: Vec::new(),
+            order_events_enabled: false,
+            last_price: dec!(0),
+            bid_stop_index: BTreeMap::new(),
+            ask_stop_index: BTreeMap::new(),
+            stop_order_index: HashMap::new(),
+        }
+    } //}}}
+
+    pub fn set_market_config(&mut self, config: MarketConfig) {
+        self.config = config;
+    }
+
+    pub fn set_post_only_mode(&mut self, mode: PostOnlyMode) {
+        self.post_only_mode = mode;
+    }
+
+    pub fn set_self_trade_prevention(&mut self, mode: SelfTradePrevention) {
+        self.stp_mode = mode;
+    }
+
+    /// turn on or off the l3 order events, the queue position costs a walk of the price level
+    pub fn set_order_events(&mut self, enabled: bool) {
+        self.order_events_enabled = enabled;
+        if !enabled {
+            self.order_events.clear();
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn set_snapshot_mode(&mut self, mode: SnapshotMode, dir: &str) {
+        self.snapshot_mode = mode;
+        self.snapshot_dir = dir.to_owned();
+    }
+
+    pub fn market(&self) -> &str {
+        &self.market
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn trade_seq(&self) -> u64 {
+        self.trade_seq
+    }
+
+    /// handle the message with its journal sequence number at the time of the clock
+    pub fn apply(&mut self, seq: u64, msg: Msg) -> Option<Result<MatchResult, TradeError>> {
+        let time_stamp = self.clock.now();
+        self.apply_at(seq, time_stamp, msg)
+    }
+
+    /// handle the message with its journal sequence number and journal time,
+    /// the replay gets the same trade records as the first run
+    pub fn apply_at(
+        &mut self,
+        seq: u64,
+        time_stamp: u64,
+        msg: Msg,
+    ) -> Option<Result<MatchResult, TradeError>> {
+        self.seq = seq;
+        self.time_stamp = time_stamp;
+        self.dispatch(msg)
+    }
+
+    /// handle one message of the orderbook at the time of the clock, the message is numbered
+    /// after the last handled one. the message without match result returns None
+    pub fn handle(&mut self, msg: Msg) -> Option<Result<MatchResult, TradeError>> {
+        self.time_stamp = self.clock.now();
+        self.seq += 1;
+        self.dispatch(msg)
+    }
+
+    fn dispatch(&mut self, msg: Msg) -> Option<Result<MatchResult, TradeError>> {
+        //{{{
+        match msg {
+            Msg::SimpleOrder(mut order) => Some(self.match_entry(&mut order)),
+
+            Msg::CancelOrder((order_id, uid)) => {
+                let mut order = OrderInfo::default();
+                order.id = order_id;
+                order.uid = uid;
+                order.op = OrderOp::Cancel;
+                Some(self.match_entry(&mut order))
+            }
+
+            Msg::Snapshot => match self.snapshot() {
+                Ok(()) => None,
+                Err(_) => Some(Err(TradeError::SnapshotFailed)),
+            },
+
+            Msg::CancelAllOrder => Some(Ok(self.mass_cancel(MassCancel::All))),
+
+            Msg::MassCancel(filter) => Some(Ok(self.mass_cancel(filter))),
+        }
+    } //}}}
+
+    /// run the orderbook in its own thread. the messages are matched in arrival order and
+    /// their results are sent to the output channel, the snapshot failures as well. with the
+    /// journal, the messages are written and fsync'd in batches before they are matched, and the
+    /// thread stops at the journal failure. otherwise it stops after handling the messages
+    /// already sent when it receives the closed signal, and gives back the orderbook
+    pub fn run(
+        mut self,
+        recv: Receiver<Msg>,
+        signal: Receiver<Signal>,
+        output: Sender<Result<MatchResult, TradeError>>,
+        mut journal: Option<Journal>,
+    ) -> JoinHandle<OrderBook> {
+        //{{{
+        thread::spawn(move || {
+            let batch_size = journal.as_ref().map_or(1, |journal| journal.batch_size());
+            loop {
+                select! {
+                    recv(signal) -> sig => match sig {
+                        Ok(Signal::CancelAllOrder) => {
+                            if !self.run_batch(vec![Msg::CancelAllOrder], &mut journal, &output) {
+                                return self;
+                            }
+                        }
+                        // closed signal, or the signal sender is gone
+                        Ok(Signal::Closed) | Err(_) => break,
+                    },
+                    recv(recv) -> msg => match msg {
+                        Ok(msg) => {
+                            let mut batch = vec![msg];
+                            batch.extend(recv.try_iter().take(batch_size - 1));
+                            if !self.run_batch(batch, &mut journal, &output) {
+                                return self;
+                            }
+                        }
+                        // all message senders are gone
+                        Err(_) => break,
+                    },
+                }
+            }
+
+            let batch: Vec<Msg> = recv.try_iter().collect();
+            if self.run_batch(batch, &mut journal, &output) {
+                let snapshot = self.wait_snapshot();
+                Self::report_snapshot(snapshot, &output);
+            }
+            self
+        })
+    } //}}}
+
+    // journal and handle a batch of messages, the orderbook stops if the journal fails.
+    // every message of the failed batch gets the journal error and none of them is handled
+    fn run_batch(
+        &mut self,
+        batch: Vec<Msg>,
+        journal: &mut Option<Journal>,
+        output: &Sender<Result<MatchResult, TradeError>>,
+    ) -> bool {
+        //{{{
+        let mut seqs = Vec::new();
+        let time_stamp = self.clock.now();
+        if let Some(journal) = journal {
+            match journal.append_batch(&batch, time_stamp) {
+                Ok(batch_seqs) => seqs = batch_seqs,
+                Err(_) => {
+                    for _ in batch.iter() {
+                        let _ = output.send(Err(TradeError::JournalFailed));
+                    }
+                    return false;
+                }
+            }
+        }
+
+        for (i, msg) in batch.into_iter().enumerate() {
+            // the messages of one batch share the journal time
+            let result = match seqs.get(i) {
+                Some(seq) => self.apply_at(*seq, time_stamp, msg),
+                // without the journal the orderbook numbers the messages itself
+                None => self.apply_at(self.seq + 1, time_stamp, msg),
+            };
+            if let Some(result) = result {
+                let _ = output.send(result);
+            }
+        }
+        // reap the finished snapshot child
+        let snapshot = self.poll_snapshot();
+        Self::report_snapshot(snapshot, output);
+        true
+    } //}}}
+
+    // send the failure of the finished snapshot child to the output channel
+    fn report_snapshot(
+        snapshot: Option<SnapshotResult>,
+        output: &Sender<Result<MatchResult, TradeError>>,
+    ) {
+        if let Some(false) = snapshot.map(|snapshot| snapshot.success()) {
+            let _ = output.send(Err(TradeError::SnapshotFailed));
+        }
+    }
+
+    // orderbook match entry, the illegal order is rejected before touching the orderbook
+    pub fn match_entry(&mut self, order: &mut OrderInfo) -> Result<MatchResult, TradeError> {
+        //{{{
+        self.check_order(order)?;
+        let mut result = self.match_order(order)?;
+
+        // the stop orders triggered by the new last price are matched one by one,
+        // they may trigger other stop orders again
+        let mut triggered = self.take_triggered_stops();
+        while !triggered.is_empty() {
+            let mut stop = triggered.remove(0);
+            let stop_result = match self.match_order(&mut stop) {
+                Ok(stop_result) => stop_result,
+                Err(_) => {
+                    // the stop order has left the trigger index, reject it with a record
+                    let mut stop_result = MatchResult::default();
+                    stop_result.order_id = stop.id;
+                    Self::reject_taker(&mut stop, &mut stop_result);
+                    stop_result.taker_status = stop.status;
+                    stop_result
+                }
+            };
+            result.triggered.push(stop_result);
+            triggered.extend(self.take_triggered_stops());
+        }
+        self.stamp_records(&mut result);
+        result.depth_updates = self.take_depth_updates();
+        result.order_events = std::mem::take(&mut self.order_events);
+        Ok(result)
+    } //}}}
+
+    /// check the order before matching.
+    /// the new order must follow the market config, and its id must not be in the orderbook
+    fn check_order(&self, order: &OrderInfo) -> Result<(), TradeError> {
+        //{{{
+        match order.op {
+            OrderOp::Cancel => return Ok(()),
+            OrderOp::Amend => {
+                if !self.config.price_legal(order.price) {
+                    return Err(TradeError::OrderPriceIllegal);
+                }
+                if !self.config.qty_legal(order.raw_qty) {
+                    return Err(TradeError::OrderQtyIllegal);
+                }
+                if !self.config.notional_legal(order.price, order.raw_qty) {
+                    return Err(TradeError::OrderNotionalIllegal);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let price_required = matches!(order.op, OrderOp::Limit | OrderOp::StopLimit);
+        if price_required && !self.config.price_legal(order.price) {
+            return Err(TradeError::OrderPriceIllegal);
+        }
+
+        let stop = matches!(order.op, OrderOp::StopMarket | OrderOp::StopLimit);
+        if stop && !self.config.price_legal(order.trigger_price) {
+            return Err(TradeError::OrderPriceIllegal);
+        }
+
+        if order.remain_qty.is_zero()
+            || !order.remain_qty.is_sign_positive()
+            || order.remain_qty > order.raw_qty
+            || !self.config.display_legal(order.display_qty)
+        {
+            return Err(TradeError::OrderQtyIllegal);
+        }
+
+        if order.qty_type == QtyType::Quote && !price_required {
+            // the qty of market order sized in quote funds is the order value
+            if !self.config.notional_legal(dec!(1), order.raw_qty) {
+                return Err(TradeError::OrderNotionalIllegal);
+            }
+        } else {
+            if !self.config.qty_legal(order.raw_qty) {
+                return Err(TradeError::OrderQtyIllegal);
+            }
+            let price = match order.op {
+                OrderOp::Limit | OrderOp::StopLimit => order.price,
+                OrderOp::StopMarket => order.trigger_price,
+                // the trade price of market order is unknown
+                _ => dec!(0),
+            };
+            if !price.is_zero() && !self.config.notional_legal(price, order.raw_qty) {
+                return Err(TradeError::OrderNotionalIllegal);
+            }
+        }
+
+        if self.order_index.contains_key(&order.id) || self.stop_order_index.contains_key(&order.id)
+        {
+            return Err(TradeError::OrderIdDuplicate);
+        }
+        Ok(())
+    } //}}}
+
+    fn match_order(&mut self, order: &mut OrderInfo) -> Result<MatchResult, TradeError> {
+        //{{{
+        let mut result = MatchResult::default();
+        result.order_id = order.id;
+        match order.op {
+            OrderOp::Limit => self.limit_match(order, &mut result)?,
+
+            OrderOp::Market => self.market_match(order, &mut result)?,
+
+            OrderOp::Cancel => result.trades.push(self.cancel(order)?),
+
+            OrderOp::Amend => self.amend(order, &mut result)?,
+
+            OrderOp::StopMarket | OrderOp::StopLimit => {
+                if self.stop_triggered(order) {
+                    Self::activate_stop(order);
+                    if order.op == OrderOp::Limit {
+                        self.limit_match(order, &mut result)?;
+                    } else {
+                        self.market_match(order, &mut result)?;
+                    }
+                } else {
+                    self.insert_stop_order(order);
+                }
+            }
+        }
+        result.taker_status = order.status;
+        Ok(result)
+    } //}}}
+
+    /// limit price match
+    /// If there are remainning parts after the order is matched. match engine will insert this part into the order book
+    fn limit_match(
+        &mut self,
+        taker: &mut OrderInfo,
+        result: &mut MatchResult,
+    ) -> Result<(), TradeError> {
+        //{{{
+        if taker.op != OrderOp::Limit {
+            return Err(TradeError::OrderOpIllegal);
+        }
+
+        if taker.time_in_force == TimeInForce::FOK && !self.fillable(taker) {
+            // reject the FOK order without touching the orderbook
+            result.rejected_qty = taker.remain_qty;
+            result.trades.push(taker.cancel_remain());
+            return Ok(());
+        }
+
+        if taker.post_only && !self.post_only_check(taker) {
+            // the post only order would be a taker
+            result.rejected_qty = taker.remain_qty;
+            result.trades.push(taker.cancel_remain());
+            return Ok(());
+        }
+
+        if self.match_book(taker, result).is_err() {
+            // the remain part must not rest in the crossed orderbook
+            Self::reject_taker(taker, result);
+            return Ok(());
+        }
+
+        if taker.remain_qty.is_zero() || !taker.remain_qty.is_sign_positive() {
+            return Ok(());
+        }
+
+        match taker.time_in_force {
+            TimeInForce::GTC => {
+                // only the display slice of iceberg order is visible in the orderbook
+                let display_qty = self
+                    .config
+                    .iceberg_slice(taker.display_qty, taker.remain_qty);
+                taker.hidden_qty = taker.remain_qty - display_qty;
+                taker.remain_qty = display_qty;
+                match self.insert_order(taker) {
+                    Ok(()) => result.rested = Some(*taker),
+                    Err(_) => {
+                        // the remain part can not rest in the orderbook
+                        result.rejected_qty = taker.remain_qty + taker.hidden_qty;
+                        result.trades.push(taker.cancel_remain());
+                    }
+                }
+            }
+            TimeInForce::IOC | TimeInForce::FOK => {
+                // the remain part of IOC order will not rest in the orderbook
+                result.rejected_qty = taker.remain_qty;
+                result.trades.push(taker.cancel_remain());
+            }
+        }
+        Ok(())
+    } //}}}
+
+    /// market price match
+    /// When the order is market type they will not be write into the order book.
+    /// If there are remainning parts after the order is matched. match engine will reject this parts and gennerate a trade reocrd for this parts
+    fn market_match(
+        &mut self,
+        taker: &mut OrderInfo,
+        result: &mut MatchResult,
+    ) -> Result<(), TradeError> {
+        //{{{
+        if taker.op != OrderOp::Market {
+            return Err(TradeError::OrderOpIllegal);
+        }
+
+        // the matching may also stop at a trade which can not be made
+        let _ = self.match_book(taker, result);
+
+        if !taker.remain_qty.is_zero() && taker.remain_qty.is_sign_positive() {
+            // there is no more suitable maker order, reject the remain part
+            Self::reject_taker(taker, result);
+        }
+        Ok(())
+    } //}}}
+
+    // reject the remain part of the taker, the trades already made stay in the result
+    fn reject_taker(taker: &mut OrderInfo, result: &mut MatchResult) {
+        result.rejected_qty = taker.remain_qty;
+        result.trades.push(taker.reject_remain());
+    }
+
+    // check whether the last price has moved through the trigger price of the stop order
+    fn stop_triggered(&self, order: &OrderInfo) -> bool {
+        if self.last_price.is_zero() {
+            return false;
+        }
+        match order.side {
+            OrderSide::Ask => self.last_price <= order.trigger_price,
+            OrderSide::Bid => self.last_price >= order.trigger_price,
+        }
+    }
+
+    // turn the triggered stop order into a normal market or limit order
+    fn activate_stop(order: &mut OrderInfo) {
+        order.op = match order.op {
+            OrderOp::StopLimit => OrderOp::Limit,
+            _ => OrderOp::Market,
+        };
+    }
+
+    // keep the stop order in the trigger index, off the visible orderbook
+    fn insert_stop_order(&mut self, order: &mut OrderInfo) {
+        //{{{
+        let index = match order.side {
+            OrderSide::Ask => &mut self.ask_stop_index,
+            OrderSide::Bid => &mut self.bid_stop_index,
+        };
+        index
+            .entry(order.trigger_price)
+            .or_insert_with(Vec::new)
+            .push(*order);
+        self.stop_order_index
+            .insert(order.id, (order.side, order.trigger_price));
+    } //}}}
+
+    /// remove the stop orders triggered by the last price from the trigger index.
+    /// buy stops come first from the lowest trigger price, then sell stops from the highest,
+    /// orders with the same trigger price keep their arrival order.
+    fn take_triggered_stops(&mut self) -> Vec<OrderInfo> {
+        //{{{
+        let mut triggered = Vec::new();
+        if self.last_price.is_zero() {
+            return triggered;
+        }
+
+        loop {
+            match self.bid_stop_index.first_entry() {
+                Some(entry) if *entry.key() <= self.last_price => triggered.extend(entry.remove()),
+                _ => break,
+            }
+        }
+        loop {
+            match self.ask_stop_index.last_entry() {
+                Some(entry) if *entry.key() >= self.last_price => triggered.extend(entry.remove()),
+                _ => break,
+            }
+        }
+
+        for order in triggered.iter_mut() {
+            self.stop_order_index.remove(&order.id);
+            Self::activate_stop(order);
+        }
+        triggered
+    } //}}}
+
+    /// match the taker with the opposite side price nodes, from the best price node
+    /// until the taker is filled or there is no suitable price node.
+    fn match_book(
+        &mut self,
+        taker: &mut OrderInfo,
+        result: &mut MatchResult,
+    ) -> Result<(), TradeError> {
+        //{{{
+        let maker_side = match taker.side {
+            OrderSide::Ask => OrderSide::Bid,
+            OrderSide::Bid => OrderSide::Ask,
+        };
+
+        loop {
+            if taker.remain_qty.is_zero() || !taker.remain_qty.is_sign_positive() {
+                break;
+            }
+
+            let leader = match maker_side {
+                OrderSide::Ask => self.ask_leader,
+                OrderSide::Bid => self.bid_leader,
+            };
+            if leader.qty.is_zero() || leader.price.is_zero() {
+                // there is no maker order
+                break;
+            }
+
+            if taker.op == OrderOp::Limit {
+                let suitable = match taker.side {
+                    OrderSide::Ask => leader.price >= taker.price,
+                    OrderSide::Bid => leader.price <= taker.price,
+                };
+                if !suitable {
+                    break;
+                }
+            }
+
+            let trade_num = result.trades.len();
+            self.match_price_node(leader.price, taker, result)?;
+            if result.trades.len() == trade_num {
+                // the taker can not trade with this price node any more
+                break;
+            }
+        }
+        Ok(())
+    } //}}}
+
+    /// check whether the post only order can rest in the orderbook as a maker.
+    /// the crossed order will be repriced one tick away from the opposite leader in reprice mode
+    fn post_only_check(&self, order: &mut OrderInfo) -> bool {
+        //{{{
+        let leader = match order.side {
+            OrderSide::Ask => self.bid_leader,
+            OrderSide::Bid => self.ask_leader,
+        };
+        if leader.qty.is_zero() || leader.price.is_zero() {
+            return true;
+        }
+
+        let crossed = match order.side {
+            OrderSide::Ask => order.price <= leader.price,
+            OrderSide::Bid => order.price >= leader.price,
+        };
+        if !crossed {
+            return true;
+        }
+
+        if self.post_only_mode == PostOnlyMode::Reject || self.config.price_tick.is_zero() {
+            return false;
+        }
+
+        let price = match order.side {
+            OrderSide::Ask => leader.price + self.config.price_tick,
+            OrderSide::Bid => leader.price - self.config.price_tick,
+        };
+        if price.is_zero() || !price.is_sign_positive() {
+            return false;
+        }
+        order.price = price;
+        true
+    } //}}}
+
+    /// check whether the opposite side orders up to the taker limit price
+    /// have enough qty to fill the whole taker order. the hidden reserve of iceberg order
+    /// is counted, the orders of the same uid are not when the self trade prevention is on
+    fn fillable(&self, taker: &OrderInfo) -> bool {
+        //{{{
+        let price_nodes: Vec<&PriceNode> = match taker.side {
+            OrderSide::Ask => self
+                .bid_price_index
+                .iter()
+                .rev()
+                .take_while(|(price, _)| **price >= taker.price)
+                .map(|(_, price_node)| price_node)
+                .collect(),
+            OrderSide::Bid => self
+                .ask_price_index
+                .iter()
+                .take_while(|(price, _)| **price <= taker.price)
+                .map(|(_, price_node)| price_node)
+                .collect(),
+        };
+
+        let mut qty = dec!(0);
+        for price_node in price_nodes {
+            let mut slot = price_node.order_slot;
+            while slot != 0 {
+                let maker = &self.orders[slot];
+                slot = maker.logic.next_slot;
+                if self.stp_mode != SelfTradePrevention::None && maker.uid == taker.uid {
+                    if self.stp_mode == SelfTradePrevention::CancelOldest {
+                        // the maker will be canceled without trading
+                        continue;
+                    }
+                    // the taker will be canceled or decremented at this maker
+                    return false;
+                }
+                qty += maker.remain_qty + maker.hidden_qty;
+                if qty >= taker.remain_qty {
+                    return true;
+                }
+            }
+        }
+        false
+    } //}}}
+
+    /// match the taker with the orders of one price node in time priority
+    fn match_price_node(
+        &mut self,
+        price: Decimal,
+        taker: &mut OrderInfo,
+        result: &mut MatchResult,
+    ) -> Result<(), TradeError> {
+        //{{{
+        let maker_side = match taker.side {
+            OrderSide::Ask => OrderSide::Bid,
+            OrderSide::Bid => OrderSide::Ask,
+        };
+        self.touch_level(maker_side, price);
+        let mut node = match self.price_index(maker_side).get(&price) {
+            Some(node) => *node,
+            None => return Ok(()),
+        };
+
+        let lot_size = self.config.lot_size();
+        let mut status = Ok(());
+        let mut maker_slot = node.order_slot;
+        loop {
+            if maker_slot == 0 || taker.remain_qty.is_zero() {
+                break;
+            }
+
+            if !self.orders[maker_slot].logic.used {
+                // the price node links to a free slot, stop matching and keep the node as it is
+                status = Err(TradeError::OrderNotFound);
+                break;
+            }
+
+            if self.stp_mode != SelfTradePrevention::None
+                && self.orders[maker_slot].uid == taker.uid
+            {
+                self.prevent_self_trade(&mut node, taker, result);
+                maker_slot = node.order_slot;
+                continue;
+            }
+
+            // the remain funds of market order can not trade any qty at this price
+            if taker.op == OrderOp::Market
+                && taker.qty_type == QtyType::Quote
+                && taker.remain_qty.checked_div(price) == Some(dec!(0))
+            {
+                break;
+            }
+
+            let mut record = match self.orders[maker_slot].trade(taker, lot_size) {
+                Ok(record) => record,
+                Err(err) => {
+                    // neither order is changed by the refused trade
+                    status = Err(err);
+                    break;
+                }
+            };
+            node.qty -= record.trade_qty;
+            self.last_price = record.trade_price;
+            self.push_order_event(OrderEventKind::Execute, maker_slot, record.trade_qty);
+
+            // the remain qty of iceberg maker includes its hidden reserve
+            let hidden_qty = self.orders[maker_slot].hidden_qty;
+            match maker_side {
+                OrderSide::Ask => record.ask_remain_qty += hidden_qty,
+                OrderSide::Bid => record.bid_remain_qty += hidden_qty,
+            }
+            result.trades.push(record);
+
+            if !self.orders[maker_slot].remain_qty.is_zero() {
+                break;
+            }
+
+            if !hidden_qty.is_zero() {
+                // refill the display slice of iceberg order from the hidden reserve,
+                // the order loses its time priority and moves to the tail of the price node
+                self.refill_iceberg(maker_slot, &mut node);
+                let display_qty = self.orders[maker_slot].remain_qty;
+                self.push_order_event(OrderEventKind::Add, maker_slot, display_qty);
+                maker_slot = node.order_slot;
+                continue;
+            }
+
+            // the maker order is filled, remove it from the price node
+            result.filled_makers.push(self.orders[maker_slot]);
+            self.pop_head_order(&mut node);
+            maker_slot = node.order_slot;
+        }
+
+        if node.order_slot == 0 {
+            self.price_index(maker_side).remove(&price);
+        } else {
+            self.price_index(maker_side).insert(price, node);
+        }
+        self.refresh_leader(maker_side);
+        status
+    } //}}}
+
+    // remove the head order from the price node which is being matched
+    fn pop_head_order(&mut self, node: &mut PriceNode) {
+        //{{{
+        let slot = node.order_slot;
+        self.orders[slot].logic.used = false;
+        self.order_bitmap.clear(&slot);
+        self.order_index.remove(&self.orders[slot].id);
+
+        let next = self.orders[slot].logic.next_slot;
+        node.order_slot = next;
+        if next != 0 {
+            self.orders[next].logic.pre_slot = 0;
+        }
+    } //}}}
+
+    /// prevent the taker from trading with the head order of the price node which has the same uid.
+    /// every canceled or decremented part gennerates a cancel trade record
+    fn prevent_self_trade(
+        &mut self,
+        node: &mut PriceNode,
+        taker: &mut OrderInfo,
+        result: &mut MatchResult,
+    ) {
+        //{{{
+        let slot = node.order_slot;
+        let mut mode = self.stp_mode;
+        if mode == SelfTradePrevention::DecrementAndCancel
+            && taker.op == OrderOp::Market
+            && taker.qty_type == QtyType::Quote
+        {
+            // the market order qty is quote funds, it can not be compared with the maker qty
+            mode = SelfTradePrevention::CancelNewest;
+        }
+
+        let cancel_maker =
+            |book: &mut OrderBook, node: &mut PriceNode, result: &mut MatchResult| {
+                let mut maker = book.orders[slot];
+                book.push_order_event(OrderEventKind::Delete, slot, maker.remain_qty);
+                node.qty -= maker.remain_qty;
+                result.trades.push(maker.cancel_remain());
+                book.orders[slot] = maker;
+                book.pop_head_order(node);
+            };
+
+        let cancel_taker = |taker: &mut OrderInfo, result: &mut MatchResult| {
+            result.rejected_qty += taker.remain_qty;
+            result.trades.push(taker.cancel_remain());
+            // the remain part is canceled, stop matching
+            taker.remain_qty = dec!(0);
+        };
+
+        match mode {
+            SelfTradePrevention::None => {}
+            SelfTradePrevention::CancelNewest => cancel_taker(taker, result),
+            SelfTradePrevention::CancelOldest => cancel_maker(self, node, result),
+            SelfTradePrevention::CancelBoth => {
+                cancel_maker(self, node, result);
+                cancel_taker(taker, result);
+            }
+            SelfTradePrevention::DecrementAndCancel => {
+                let maker_qty = self.orders[slot].remain_qty + self.orders[slot].hidden_qty;
+                let qty = if maker_qty < taker.remain_qty {
+                    maker_qty
+                } else {
+                    taker.remain_qty
+                };
+
+                if qty == maker_qty {
+                    cancel_maker(self, node, result);
+                } else {
+                    let maker = &mut self.orders[slot];
+                    result.trades.push(Self::decrement_record(maker, qty));
+
+                    // the hidden reserve of iceberg order is decremented first
+                    let hidden_qty = if maker.hidden_qty > qty {
+                        qty
+                    } else {
+                        maker.hidden_qty
+                    };
+                    maker.hidden_qty -= hidden_qty;
+                    maker.remain_qty -= qty - hidden_qty;
+                    maker.raw_qty -= qty;
+                    node.qty -= qty - hidden_qty;
+                    if qty > hidden_qty {
+                        self.push_order_event(OrderEventKind::Reduce, slot, qty - hidden_qty);
+                    }
+                }
+
+                if qty == taker.remain_qty {
+                    cancel_taker(taker, result);
+                } else {
+                    result.trades.push(Self::decrement_record(taker, qty));
+                    taker.remain_qty -= qty;
+                    taker.raw_qty -= qty;
+                }
+            }
+        }
+    } //}}}
+
+    // gennerate the cancel trade record for the decremented part of the order
+    fn decrement_record(order: &OrderInfo, qty: Decimal) -> TradeRecord {
+        let mut part = *order;
+        part.remain_qty = qty;
+        part.hidden_qty = dec!(0);
+        part.cancel_remain()
+    }
+
+    // refill the iceberg order and move it to the tail of the price node
+    fn refill_iceberg(&mut self, slot: usize, node: &mut PriceNode) {
+        //{{{
+        let display_qty = self
+            .config
+            .iceberg_slice(self.orders[slot].display_qty, self.orders[slot].hidden_qty);
+        let order = &mut self.orders[slot];
+        order.remain_qty = display_qty;
+        order.hidden_qty -= display_qty;
+        order.status = OrderStatus::PartTrade;
+        order.logic.used = true;
+        node.qty += display_qty;
+
+        let next = order.logic.next_slot;
+        if next == 0 {
+            // already the tail of the price node
+            return;
+        }
+
+        let last_slot = node.last_slot;
+        order.logic.pre_slot = last_slot;
+        order.logic.next_slot = 0;
+
+        node.order_slot = next;
+        self.orders[next].logic.pre_slot = 0;
+        self.orders[last_slot].logic.next_slot = slot;
+        node.last_slot = slot;
+    } //}}}
+
+    /// amend the price or quantity of the order in the orderbook. the amend order carries the
+    /// new price and the new raw qty, which will be filled with the amended order info.
+    /// reducing qty at the same price keeps the queue position, otherwise the order is
+    /// matched again with the new price and re-queued at the tail of the price node.
+    fn amend(&mut self, amend: &mut OrderInfo, result: &mut MatchResult) -> Result<(), TradeError> {
+        //{{{
+        if amend.op != OrderOp::Amend {
+            return Err(TradeError::OrderOpIllegal);
+        }
+
+        let slot = match self.order_index.get(&amend.id) {
+            Some(slot) if self.orders[*slot].uid == amend.uid => *slot,
+            _ => return Err(TradeError::OrderNotFound),
+        };
+        if self.orders[slot].side != amend.side {
+            return Err(TradeError::OrderSideIllegal);
+        }
+
+        let mut order = self.orders[slot];
+        let remain_qty = order.remain_qty + order.hidden_qty;
+        let new_remain_qty = amend.raw_qty - order.trade_qty;
+
+        if new_remain_qty.is_zero() || !new_remain_qty.is_sign_positive() {
+            // the new qty has been traded already, cancel the order
+            order = self.remove_order(slot);
+            result.trades.push(order.cancel_remain());
+            *amend = order;
+            return Ok(());
+        }
+
+        if amend.price == order.price && new_remain_qty <= remain_qty {
+            // reduce qty in place, the hidden reserve of iceberg order is reduced first
+            let reduce_qty = remain_qty - new_remain_qty;
+            let hidden_reduce_qty = if order.hidden_qty > reduce_qty {
+                reduce_qty
+            } else {
+                order.hidden_qty
+            };
+            let display_reduce_qty = reduce_qty - hidden_reduce_qty;
+
+            order.raw_qty = amend.raw_qty;
+            order.hidden_qty -= hidden_reduce_qty;
+            order.remain_qty -= display_reduce_qty;
+            self.orders[slot] = order;
+
+            self.touch_level(order.side, order.price);
+            if let Some(price_node) = self.price_index(order.side).get_mut(&order.price) {
+                price_node.qty -= display_reduce_qty;
+            }
+            self.refresh_leader(order.side);
+            if !display_reduce_qty.is_zero() {
+                self.push_order_event(OrderEventKind::Reduce, slot, display_reduce_qty);
+            }
+            *amend = order;
+            return Ok(());
+        }
+
+        // the order loses its time priority
+        order = self.remove_order(slot);
+        order.price = amend.price;
+        order.raw_qty = amend.raw_qty;
+        order.remain_qty = new_remain_qty;
+        order.hidden_qty = dec!(0);
+        order.logic.used = true;
+        self.limit_match(&mut order, result)?;
+        *amend = order;
+        Ok(())
+    } //}}}
+
+    /// cancel the orders chosen by the filter in one pass,
+    /// every canceled order gennerates a cancel trade record
+    pub fn mass_cancel(&mut self, filter: MassCancel) -> MatchResult {
+        //{{{
+        let mut result = MatchResult::default();
+        let mut slots: Vec<usize> = self
+            .order_index
+            .values()
+            .cloned()
+            .filter(|slot| filter.matches(&self.orders[*slot], false))
+            .collect();
+        slots.sort();
+        for slot in slots {
+            let mut order = self.remove_order(slot);
+            result.trades.push(order.cancel_remain());
+        }
+
+        let stop_order_index = &mut self.stop_order_index;
+        for index in [&mut self.bid_stop_index, &mut self.ask_stop_index].iter_mut() {
+            index.retain(|_, orders| {
+                orders.retain(|order| {
+                    if !filter.matches(order, true) {
+                        return true;
+                    }
+                    stop_order_index.remove(&order.id);
+                    let mut order = *order;
+                    result.trades.push(order.cancel_remain());
+                    false
+                });
+                !orders.is_empty()
+            });
+        }
+        self.stamp_records(&mut result);
+        result.depth_updates = self.take_depth_updates();
+        result.order_events = std::mem::take(&mut self.order_events);
+        result
+    } //}}}
+
+    // assign the trade ids in produced order and the message time to the trade records
+    fn stamp_records(&mut self, result: &mut MatchResult) {
+        //{{{
+        for record in result.trades.iter_mut() {
+            self.trade_seq += 1;
+            record.trade_id = self.trade_seq;
+            record.time_stamp = self.time_stamp;
+        }
+        for triggered in result.triggered.iter_mut() {
+            self.stamp_records(triggered);
+        }
+    } //}}}
+
+    // cancel order by order id, only the order owner can cancel it.
+    // the cancel order will be filled with the canceled order info
+    fn cancel(&mut self, order: &mut OrderInfo) -> Result<TradeRecord, TradeError> {
+        //{{{
+        if order.op != OrderOp::Cancel {
+            return Err(TradeError::OrderOpIllegal);
+        }
+
+        let slot = match self.order_index.get(&order.id) {
+            Some(slot) => *slot,
+            // the order may be a stop order waiting for trigger
+            None => return self.cancel_stop_order(order),
+        };
+
+        if self.orders[slot].uid != order.uid {
+            return Err(TradeError::OrderNotFound);
+        }
+
+        *order = self.remove_order(slot);
+        Ok(order.cancel_remain())
+    } //}}}
+
+    // cancel the stop order in the trigger index
+    fn cancel_stop_order(&mut self, order: &mut OrderInfo) -> Result<TradeRecord, TradeError> {
+        //{{{
+        let (side, trigger_price) = match self.stop_order_index.get(&order.id) {
+            Some(key) => *key,
+            None => return Err(TradeError::OrderNotFound),
+        };
+        let index = match side {
+            OrderSide::Ask => &mut self.ask_stop_index,
+            OrderSide::Bid => &mut self.bid_stop_index,
+        };
+        let orders = match index.get_mut(&trigger_price) {
+            Some(orders) => orders,
+            None => return Err(TradeError::OrderNotFound),
+        };
+        let i = match orders
+            .iter()
+            .position(|o| o.id == order.id && o.uid == order.uid)
+        {
+            Some(i) => i,
+            None => return Err(TradeError::OrderNotFound),
+        };
+
+        let mut stop = orders.remove(i);
+        if orders.is_empty() {
+            index.remove(&trigger_price);
+        }
+        self.stop_order_index.remove(&order.id);
+
+        let record = stop.cancel_remain();
+        *order = stop;
+        Ok(record)
+    } //}}}
+
+    /// remove the order from the price node list of the order book.
+    /// the price node will be removed if there is no order in it.
+    fn remove_order(&mut self, slot: usize) -> OrderInfo {
+        //{{{
+        let order = self.orders[slot];
+        self.touch_level(order.side, order.price);
+        let mut price_node = match self.price_index(order.side).get(&order.price) {
+            Some(price_node) => *price_node,
+            None => return order,
+        };
+        self.push_order_event(OrderEventKind::Delete, slot, order.remain_qty);
+
+        price_node.qty -= order.remain_qty;
+
+        if order.logic.pre_slot == 0 {
+            price_node.order_slot = order.logic.next_slot;
+        } else {
+            self.orders[order.logic.pre_slot].logic.next_slot = order.logic.next_slot;
+        }
+
+        if order.logic.next_slot == 0 {
+            price_node.last_slot = order.logic.pre_slot;
+        } else {
+            self.orders[order.logic.next_slot].logic.pre_slot = order.logic.pre_slot;
+        }
+
+        self.orders[slot].logic.used = false;
+        self.order_bitmap.clear(&slot);
+        self.order_index.remove(&order.id);
+
+        if price_node.order_slot == 0 {
+            // remove this price node
+            self.price_index(order.side).remove(&order.price);
+        } else {
+            self.price_index(order.side).insert(order.price, price_node);
+        }
+        self.refresh_leader(order.side);
+
+        self.orders[slot]
+    } //}}}
+
+    /// the top n price levels of both sides
+    pub fn depth(&self, n: usize) -> Depth {
+        //{{{
+        let level = |(price, node): (&Decimal, &PriceNode)| PriceLevel {
+            price: *price,
+            qty: node.qty,
+        };
+        Depth {
+            seq: self.seq,
+            bids: self
+                .bid_price_index
+                .iter()
+                .rev()
+                .take(n)
+                .map(level)
+                .collect(),
+            asks: self.ask_price_index.iter().take(n).map(level).collect(),
+        }
+    } //}}}
+
+    // remember the qty of the price level before it is changed
+    fn touch_level(&mut self, side: OrderSide, price: Decimal) {
+        //{{{
+        let key = (side as u8, price);
+        if self.depth_touched.contains_key(&key) {
+            return;
+        }
+        let qty = self
+            .price_index(side)
+            .get(&price)
+            .map_or(dec!(0), |node| node.qty);
+        self.depth_touched.insert(key, qty);
+    } //}}}
+
+    // generate the depth updates of the changed price levels since the last call
+    fn take_depth_updates(&mut self) -> Vec<DepthUpdate> {
+        //{{{
+        let mut updates = Vec::new();
+        for ((side, price), old_qty) in std::mem::take(&mut self.depth_touched) {
+            let side = if side == OrderSide::Bid as u8 {
+                OrderSide::Bid
+            } else {
+                OrderSide::Ask
+            };
+            let qty = self
+                .price_index(side)
+                .get(&price)
+                .map_or(dec!(0), |node| node.qty);
+            if qty != old_qty {
+                updates.push(DepthUpdate {
+                    seq: self.seq,
+                    side: side,
+                    price: price,
+                    qty: qty,
+                });
+            }
+        }
+        updates
+    } //}}}
+
+    // the queue position of the order in the slot in its price level, 0 is the head
+    fn queue_position(&self, slot: usize) -> usize {
+        //{{{
+        let mut position = 0;
+        let mut pre_slot = self.orders[slot].logic.pre_slot;
+        while pre_slot != 0 {
+            position += 1;
+            pre_slot = self.orders[pre_slot].logic.pre_slot;
+        }
+        position
+    } //}}}
+
+    // record the l3 event of the resting order in the slot if the l3 events are enabled
+    fn push_order_event(&mut self, kind: OrderEventKind, slot: usize, qty: Decimal) {
+        //{{{
+        if !self.order_events_enabled {
+            return;
+        }
+        let order = &self.orders[slot];
+        let event = OrderEvent {
+            seq: self.seq,
+            kind: kind,
+            order_id: order.id,
+            side: order.side,
+            price: order.price,
+            qty: qty,
+            remain_qty: order.remain_qty,
+            position: self.queue_position(slot),
+        };
+        self.order_events.push(event);
+    } //}}}
+
+    #[inline]
+    fn price_index(&mut self, side: OrderSide) -> &mut BTreeMap<Decimal, PriceNode> {
+        match side {
+            OrderSide::Ask => &mut self.ask_price_index,
+            OrderSide::Bid => &mut self.bid_price_index,
+        }
+    }
+
+    // update the leader price node of the side from the price index
+    fn refresh_leader(&mut self, side: OrderSide) {
+        //{{{
+        match side {
+            OrderSide::Ask => match self.ask_price_index.first_key_value() {
+                Some((_, price_node)) => self.ask_leader = *price_node,
+                None => self.ask_leader = PriceNode::default(),
+            },
+            OrderSide::Bid => match self.bid_price_index.last_key_value() {
+                Some((_, price_node)) => self.bid_leader = *price_node,
+                None => self.bid_leader = PriceNode::default(),
+            },
+        }
+    } //}}}
+
+    // There is no suitable price order, Insert this order into orderbook
+    fn insert_order(&mut self, order: &mut OrderInfo) -> Result<(), TradeError> {
+        //{{{
+        if order.op != OrderOp::Limit {
+            return Err(TradeError::OrderOpIllegal);
+        }
+        if self.order_index.contains_key(&order.id) {
+            return Err(TradeError::OrderIdDuplicate);
+        }
+
+        // the qty of the price node must stay in the decimal range
+        let node = match order.side {
+            OrderSide::Ask => self.ask_price_index.get(&order.price),
+            OrderSide::Bid => self.bid_price_index.get(&order.price),
+        };
+        if let Some(node) = node {
+            if node.qty.checked_add(order.remain_qty).is_none() {
+                return Err(TradeError::OrderQtyIllegal);
+            }
+        }
+
+        let slot = self.order_bitmap.find_unset();
+        if slot >= self.orders.len() {
+            // the bitmap grows beyond the order slots
+            self.order_bitmap.clear(&slot);
+            return Err(TradeError::OrderBookFull);
+        }
+        self.touch_level(order.side, order.price);
+        let price = order.price;
+        match order.side {
+            OrderSide::Ask => {
+                //{{{
+                match self.ask_price_index.get_mut(&price) {
+                    Some(price_node) => {
+                        // price node already exist
+                        price_node.qty += order.remain_qty;
+                        let last_slot = price_node.last_slot;
+                        order.logic.curr_slot = slot;
+                        order.logic.pre_slot = last_slot;
+                        order.logic.next_slot = 0usize;
+
+                        order.logic.used = true;
+
+                        self.orders[last_slot].logic.next_slot = slot;
+                        price_node.last_slot = slot;
+                        if self.ask_leader.price == order.price {
+                            self.ask_leader.last_slot = slot;
+                            self.ask_leader.qty += order.remain_qty;
+                        }
+                    }
+                    None => {
+                        // price node does not exist
+                        let new_node = PriceNode {
+                            qty: order.remain_qty,
+                            price: order.price,
+                            order_slot: slot,
+                            last_slot: slot,
+                        };
+                        order.logic.curr_slot = slot;
+                        order.logic.pre_slot = 0usize;
+                        order.logic.next_slot = 0usize;
+
+                        order.logic.used = true;
+                        if self.ask_leader.price > order.price || self.ask_leader.price.is_zero() {
+                            self.ask_leader.price = order.price;
+                            self.ask_leader.qty = order.remain_qty;
+                            self.ask_leader.order_slot = slot;
+                            self.ask_leader.last_slot = slot;
+                        }
+
+                        self.ask_price_index.insert(order.price, new_node);
+                    }
+                }
+            } //}}}
+            OrderSide::Bid => {
+                //{{{
+                match self.bid_price_index.get_mut(&price) {
+                    Some(price_node) => {
+                        // price node already exist
+                        price_node.qty += order.remain_qty;
+
+                        let last_slot = price_node.last_slot;
+
+                        order.logic.curr_slot = slot;
+                        order.logic.pre_slot = last_slot;
+                        order.logic.next_slot = 0usize;
+
+                        order.logic.used = true;
+                        self.orders[last_slot].logic.next_slot = slot;
+                        price_node.last_slot = slot;
+                        if order.price == self.bid_leader.price {
+                            self.bid_leader.qty += order.remain_qty;
+                            self.bid_leader.last_slot = slot;
+                        }
+                    }
+                    None => {
+                        // price node does not existc
+                        let new_node = PriceNode {
+                            qty: order.remain_qty,
+                            price: order.price,
+                            order_slot: slot,
+                            last_slot: slot,
+                        };
+                        order.logic.curr_slot = slot;
+                        order.logic.pre_slot = 0usize;
+                        order.logic.next_slot = 0usize;
+
+                        order.logic.used = true;
+                        if self.bid_leader.price < order.price {
+                            self.bid_leader.price = order.price;
+                            self.bid_leader.qty = order.remain_qty;
+                            self.bid_leader.order_slot = slot;
+                            self.bid_leader.last_slot = slot;
+                        }
+                        self.bid_price_index.insert(order.price, new_node);
+                    }
+                }
+            } //}}}
+        }
+        self.orders[slot] = *order;
+        self.order_index.insert(order.id, slot);
+        self.push_order_event(OrderEventKind::Add, slot, order.remain_qty);
+        Ok(())
+    } //}}}
+
+    // the failed snapshot must not stop the matching, it is reported to the caller.
+    // the fork mode only reports the fork failure here
+    fn snapshot(&mut self) -> Result<(), SnapshotError> {
+        match self.snapshot_mode {
+            SnapshotMode::Blocking => self.dump(Path::new(&self.snapshot_dir)).map(|_| ()),
+            SnapshotMode::Fork => self.fork_snapshot(),
+        }
+    }
+
+    /// fork the process at the current sequence number. the orderbook is serialized before
+    /// the fork, the child only writes the file and exits while the parent keeps matching.
+    /// only one snapshot child runs at a time
+    fn fork_snapshot(&mut self) -> Result<(), SnapshotError> {
+        //{{{
+        if self.poll_snapshot().is_none() && self.snapshot_child.is_some() {
+            return Err(SnapshotError::Io(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "the last snapshot is still running",
+            )));
+        }
+
+        // the forked child of the multi-threaded process must not allocate
+        let mut data = Vec::new();
+        snapshot::write(self, &mut data)?;
+        let path = self.snapshot_path(Path::new(&self.snapshot_dir));
+        let temp_path = path.with_extension("d.tmp");
+        let to_cstring = |path: &Path| {
+            CString::new(path.as_os_str().as_bytes())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+        };
+        let (path, temp_path) = (to_cstring(&path)?, to_cstring(&temp_path)?);
+
+        match unsafe { libc::fork() } {
+            -1 => Err(SnapshotError::Io(io::Error::last_os_error())),
+            0 => {
+                // the child only has this thread, it must exit without returning
+                let code = write_snapshot_file(&temp_path, &path, &data);
+                unsafe { libc::_exit(code) }
+            }
+            pid => {
+                self.snapshot_child = Some(SnapshotChild {
+                    pid: pid,
+                    seq: self.seq,
+                });
+                Ok(())
+            }
+        }
+    } //}}}
+
+    /// check whether the snapshot child has finished without blocking
+    pub fn poll_snapshot(&mut self) -> Option<SnapshotResult> {
+        self.reap_snapshot(libc::WNOHANG)
+    }
+
+    /// wait for the snapshot child to finish, return None if there is no snapshot child
+    pub fn wait_snapshot(&mut self) -> Option<SnapshotResult> {
+        self.reap_snapshot(0)
+    }
+
+    pub fn last_snapshot(&self) -> Option<SnapshotResult> {
+        self.last_snapshot
+    }
+
+    fn reap_snapshot(&mut self, options: libc::c_int) -> Option<SnapshotResult> {
+        //{{{
+        let child = self.snapshot_child?;
+        let mut status: libc::c_int = 0;
+        let pid = loop {
+            let pid = unsafe { libc::waitpid(child.pid, &mut status, options) };
+            if pid == -1 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break pid;
+        };
+        if pid == 0 {
+            // the child is still running
+            return None;
+        }
+
+        let exit_code = if pid == child.pid && libc::WIFEXITED(status) {
+            Some(libc::WEXITSTATUS(status))
+        } else {
+            // killed by signal, or the child is lost
+            None
+        };
+        let result = SnapshotResult {
+            pid: child.pid,
+            seq: child.seq,
+            exit_code: exit_code,
+        };
+        self.snapshot_child = None;
+        self.last_snapshot = Some(result);
+        Some(result)
+    } //}}}
+
+    /// write the binary snapshot into `<dir>/<date>_<market>.d` named by `snapshot_file_name`
+    /// with the date of the clock and return the file path. the snapshot is written into a temp
+    /// file first, the old snapshot is replaced only when the new one is complete
+    pub fn dump(&self, dir: &Path) -> Result<PathBuf, SnapshotError> {
+        //{{{
+        let path = self.snapshot_path(dir);
+        let temp_path = path.with_extension("d.tmp");
+
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        snapshot::write(self, &mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&temp_path, &path)?;
+        Ok(path)
+    } //}}}
+
+    // the snapshot file path of the market at the date of the clock
+    fn snapshot_path(&self, dir: &Path) -> PathBuf {
+        // the clock out of the date range falls back to the wall clock
+        let time = match Utc.timestamp_millis_opt(self.clock.now() as i64) {
+            LocalResult::Single(time) => time,
+            _ => Utc::now(),
+        };
+        let date = time.format("%Y-%m-%d").to_string();
+        dir.join(snapshot_file_name(&date, &self.market))
+    }
+
+    /// check the price node lists against the orders, the order index and the leaders.
+    /// return the first inconsistency found
+    pub fn check_consistency(&self) -> Result<(), String> {
+        //{{{
+        let mut order_num = 0;
+        for (side, index) in [
+            (OrderSide::Bid, &self.bid_price_index),
+            (OrderSide::Ask, &self.ask_price_index),
+        ]
+        .iter()
+        {
+            for (price, node) in index.iter() {
+                if node.price != *price {
+                    return Err(format!(
+                        "{:?} price node {} has price {}",
+                        side, price, node.price
+                    ));
+                }
+
+                let mut qty = dec!(0);
+                let mut pre_slot = 0;
+                let mut slot = node.order_slot;
+                while slot != 0 {
+                    let order = &self.orders[slot];
+                    if !order.logic.used || order.side != *side || order.price != *price {
+                        return Err(format!(
+                            "{:?} price node {} has illegal slot {}",
+                            side, price, slot
+                        ));
+                    }
+                    if order.logic.pre_slot != pre_slot {
+                        return Err(format!("slot {} has broken pre slot", slot));
+                    }
+                    if self.order_index.get(&order.id) != Some(&slot) {
+                        return Err(format!(
+                            "order {} is not indexed to slot {}",
+                            order.id, slot
+                        ));
+                    }
+                    qty += order.remain_qty;
+                    order_num += 1;
+                    pre_slot = slot;
+                    slot = order.logic.next_slot;
+                }
+
+                if node.last_slot != pre_slot {
+                    return Err(format!(
+                        "{:?} price node {} has broken last slot",
+                        side, price
+                    ));
+                }
+                if node.qty != qty {
+                    return Err(format!(
+                        "{:?} price node {} qty {} is not the order total {}",
+                        side, price, node.qty, qty
+                    ));
+                }
+            }
+        }
+        if order_num != self.order_index.len() {
+            return Err(format!(
+                "{} orders in price nodes, {} orders indexed",
+                order_num,
+                self.order_index.len()
+            ));
+        }
+
+        let bid_leader = self
+            .bid_price_index
+            .values()
+            .next_back()
+            .cloned()
+            .unwrap_or_default();
+        let ask_leader = self
+            .ask_price_index
+            .values()
+            .next()
+            .cloned()
+            .unwrap_or_default();
+        if self.bid_leader != bid_leader {
+            return Err(format!(
+                "bid leader {:?} is not {:?}",
+                self.bid_leader, bid_leader
+            ));
+        }
+        if self.ask_leader != ask_leader {
+            return Err(format!(
+                "ask leader {:?} is not {:?}",
+                self.ask_leader, ask_leader
+            ));
+        }
+        Ok(())
+    } //}}}
+}
+
+// write the data into the temp file, fsync and rename it to the path with the libc calls
+// only, so that the forked child can run it without allocating. return the exit code
+fn write_snapshot_file(temp_path: &CStr, path: &CStr, data: &[u8]) -> i32 {
+    //{{{
+    unsafe {
+        let fd = libc::open(
+            temp_path.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+            0o644,
+        );
+        if fd == -1 {
+            return 1;
+        }
+        let mut written = 0;
+        while written < data.len() {
+            let rest = &data[written..];
+            let n = libc::write(fd, rest.as_ptr() as *const libc::c_void, rest.len());
+            if n > 0 {
+                written += n as usize;
+            } else if n == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                libc::close(fd);
+                return 1;
+            }
+        }
+        if libc::fsync(fd) != 0 {
+            libc::close(fd);
+            return 1;
+        }
+        if libc::close(fd) != 0 || libc::rename(temp_path.as_ptr(), path.as_ptr()) != 0 {
+            return 1;
+        }
+    }
+    0
+} //}}}
+
+/// the snapshot file name `<date>_<market>.d`. the market is escaped so that every market
+/// gets its own file name, the bytes other than the ascii alphanumerics, '-' and '.' are
+/// written as `%XX`
+pub fn snapshot_file_name(date: &str, market: &str) -> String {
+    //{{{
+    let mut name = format!("{}_", date);
+    for byte in market.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'.' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    name + ".d"
+} //}}}
+
+/// the `%Y-%m-%d` date of the snapshot file name, None if it is not a snapshot of the market
+pub fn snapshot_date<'a>(file_name: &'a str, market: &str) -> Option<&'a str> {
+    //{{{
+    let date = file_name.get(..10)?;
+    let date_legal = date.bytes().enumerate().all(|(i, byte)| match i {
+        4 | 7 => byte == b'-',
+        _ => byte.is_ascii_digit(),
+    });
+    if date_legal && file_name == snapshot_file_name(date, market) {
+        Some(date)
+    } else {
+        None
+    }
+} //}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use order::proto::TradeType;
+
+    #[test]
+    fn order_book_init_insert_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut test_order = OrderInfo::default();
+        test_order.price = dec!(1.2);
+        test_order.id = 1;
+        test_order.raw_qty = dec!(100);
+        test_order.remain_qty = dec!(100);
+        orderbook.insert_order(&mut test_order).unwrap();
+        assert_eq!(orderbook.bid_leader.qty, dec!(100));
+        test_order.id = 2;
+        orderbook.insert_order(&mut test_order).unwrap();
+        assert_eq!(orderbook.bid_leader.qty, dec!(200));
+        test_order.id = 3;
+        orderbook.insert_order(&mut test_order).unwrap();
+        assert_eq!(orderbook.bid_leader.qty, dec!(300));
+        assert_eq!(orderbook.orders[1].logic.curr_slot, 1);
+        assert_eq!(orderbook.orders[1].logic.pre_slot, 0);
+        assert_eq!(orderbook.orders[1].logic.next_slot, 2);
+
+        assert_eq!(orderbook.orders[2].logic.curr_slot, 2);
+        assert_eq!(orderbook.orders[2].logic.pre_slot, 1);
+        assert_eq!(orderbook.orders[2].logic.next_slot, 3);
+
+        assert_eq!(orderbook.orders[3].logic.curr_slot, 3);
+        assert_eq!(orderbook.orders[3].logic.pre_slot, 2);
+        assert_eq!(orderbook.orders[3].logic.next_slot, 0);
+
+        for (k, v) in orderbook.bid_price_index.iter() {
+            println!("{}-> {:?}", k, v);
+        }
+        let price_node = orderbook.bid_price_index.get(&test_order.price).unwrap();
+        println!("{:?}", price_node);
+    } //}}}
+
+    #[test]
+    fn order_book_cancel_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut test_order = OrderInfo::default();
+        test_order.id = 1;
+        test_order.price = dec!(1.23);
+        test_order.raw_qty = dec!(100);
+        test_order.remain_qty = dec!(100);
+        orderbook.insert_order(&mut test_order).unwrap();
+        test_order.id = 2;
+        orderbook.insert_order(&mut test_order).unwrap();
+        test_order.id = 3;
+        orderbook.insert_order(&mut test_order).unwrap();
+
+        test_order.id = 2;
+        test_order.op = OrderOp::Cancel;
+        orderbook.cancel(&mut test_order).unwrap();
+        let price_node = orderbook.bid_price_index.get(&test_order.price).unwrap();
+
+        assert_eq!(price_node.qty, dec!(200));
+        assert_eq!(price_node.order_slot, 1usize);
+        assert_eq!(price_node.last_slot, 3usize);
+        assert_eq!(orderbook.orders[2].logic.used, false);
+
+        test_order.op = OrderOp::Limit;
+        test_order.side = OrderSide::Ask;
+        test_order.id = 4;
+        test_order.price = dec!(1.25);
+        orderbook.insert_order(&mut test_order).unwrap();
+
+        test_order.id = 5;
+        orderbook.insert_order(&mut test_order).unwrap();
+        test_order.id = 6;
+        orderbook.insert_order(&mut test_order).unwrap();
+        test_order.id = 5;
+        test_order.op = OrderOp::Cancel;
+        orderbook.cancel(&mut test_order).unwrap();
+
+        assert_eq!(orderbook.orders[2].logic.curr_slot, 2);
+        assert_eq!(orderbook.orders[2].logic.pre_slot, 0);
+        assert_eq!(orderbook.orders[2].logic.next_slot, 5);
+
+        assert_eq!(orderbook.orders[4].logic.curr_slot, 4);
+        assert_eq!(orderbook.orders[4].logic.pre_slot, 2);
+        assert_eq!(orderbook.orders[4].logic.next_slot, 5);
+
+        assert_eq!(orderbook.orders[5].logic.curr_slot, 5);
+        assert_eq!(orderbook.orders[5].logic.pre_slot, 2);
+        assert_eq!(orderbook.orders[5].logic.next_slot, 0);
+        let price_node = orderbook.ask_price_index.get(&test_order.price).unwrap();
+        assert_eq!(price_node.qty, dec!(200));
+        assert_eq!(price_node.order_slot, 2);
+        assert_eq!(price_node.last_slot, 5);
+    } //}}}
+
+    #[test]
+    fn order_bool_limit_match_test_bid_taker() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut test_order = OrderInfo::default();
+        test_order.id = 1;
+        test_order.price = dec!(1.23);
+        test_order.raw_qty = dec!(100);
+        test_order.remain_qty = dec!(100);
+        test_order.uid = 10001;
+        orderbook.insert_order(&mut test_order.clone()).unwrap();
+        let node = orderbook.bid_price_index.get(&dec!(1.23)).unwrap();
+        assert_eq!(node.qty, dec!(100));
+        assert_eq!(node.price, dec!(1.23));
+        assert_eq!(node.order_slot, 1usize);
+        assert_eq!(node.last_slot, 1usize);
+
+        test_order.price = dec!(1.24);
+        test_order.uid = 10002;
+        test_order.id = 2;
+        orderbook.insert_order(&mut test_order.clone()).unwrap();
+        let node = orderbook.bid_price_index.get(&dec!(1.24)).unwrap();
+        assert_eq!(node.qty, dec!(100));
+        assert_eq!(node.price, dec!(1.24));
+        assert_eq!(node.order_slot, 2usize);
+        assert_eq!(node.last_slot, 2usize);
+
+        test_order.price = dec!(1.25);
+        test_order.uid = 10003;
+        test_order.id = 3;
+        orderbook.insert_order(&mut test_order.clone()).unwrap();
+        let node = orderbook.bid_price_index.get(&dec!(1.25)).unwrap();
+        assert_eq!(node.qty, dec!(100));
+        assert_eq!(node.price, dec!(1.25));
+        assert_eq!(node.order_slot, 3usize);
+        assert_eq!(node.last_slot, 3usize);
+
+        test_order.price = dec!(1.23);
+        test_order.raw_qty = dec!(250);
+        test_order.remain_qty = dec!(250);
+        test_order.uid = 10005;
+        test_order.id = 4;
+        test_order.side = OrderSide::Ask;
+        orderbook.match_entry(&mut test_order.clone()).unwrap();
+
+        match orderbook.bid_price_index.get(&dec!(1.25)) {
+            Some(_) => panic!("error"),
+            None => (),
+        }
+        match orderbook.bid_price_index.get(&dec!(1.24)) {
+            Some(_) => panic!("error"),
+            None => (),
+        }
+        let node = orderbook.bid_price_index.get(&dec!(1.23)).unwrap();
+        assert_eq!(node.qty, dec!(50));
+        assert_eq!(node.price, dec!(1.23));
+        assert_eq!(node.order_slot, 1usize);
+        assert_eq!(node.last_slot, 1usize);
+
+        assert_eq!(orderbook.orders[1].logic.used, true);
+        assert_eq!(orderbook.orders[2].logic.used, false);
+        assert_eq!(orderbook.orders[3].logic.used, false);
+    } //}}}
+
+    #[test]
+    fn order_bool_limit_match_test_ask_taker() {
+        //{{{
+        //{{{
+
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut test_order = OrderInfo::default();
+        test_order.side = OrderSide::Ask;
+        test_order.id = 1;
+        test_order.price = dec!(1.23);
+        test_order.raw_qty = dec!(100);
+        test_order.remain_qty = dec!(100);
+        test_order.uid = 10001;
+        orderbook.insert_order(&mut test_order.clone()).unwrap();
+        let node = orderbook.ask_price_index.get(&dec!(1.23)).unwrap();
+        assert_eq!(node.qty, dec!(100));
+        assert_eq!(node.price, dec!(1.23));
+        assert_eq!(node.order_slot, 1usize);
+        assert_eq!(node.last_slot, 1usize);
+
+        test_order.price = dec!(1.24);
+        test_order.uid = 10002;
+        test_order.id = 2;
+        orderbook.insert_order(&mut test_order.clone()).unwrap();
+        let node = orderbook.ask_price_index.get(&dec!(1.24)).unwrap();
+        assert_eq!(node.qty, dec!(100));
+        assert_eq!(node.price, dec!(1.24));
+        assert_eq!(node.order_slot, 2usize);
+        assert_eq!(node.last_slot, 2usize);
+
+        test_order.price = dec!(1.25);
+        test_order.uid = 10003;
+        test_order.id = 3;
+        orderbook.insert_order(&mut test_order.clone()).unwrap();
+        let node = orderbook.ask_price_index.get(&dec!(1.25)).unwrap();
+        assert_eq!(node.qty, dec!(100));
+        assert_eq!(node.price, dec!(1.25));
+        assert_eq!(node.order_slot, 3usize);
+        assert_eq!(node.last_slot, 3usize);
+
+        test_order.price = dec!(1.25);
+        test_order.raw_qty = dec!(250);
+        test_order.remain_qty = dec!(250);
+        test_order.uid = 10005;
+        test_order.id = 4;
+        test_order.side = OrderSide::Bid;
+        orderbook.match_entry(&mut test_order.clone()).unwrap();
+
+        match orderbook.bid_price_index.get(&dec!(1.24)) {
+            Some(_) => panic!("error"),
+            None => (),
+        }
+        match orderbook.bid_price_index.get(&dec!(1.23)) {
+            Some(_) => panic!("error"),
+            None => (),
+        }
+        let node = orderbook.ask_price_index.get(&dec!(1.25)).unwrap();
+
+        assert_eq!(node.qty, dec!(50));
+        assert_eq!(node.price, dec!(1.25));
+        assert_eq!(node.order_slot, 3usize);
+        assert_eq!(node.last_slot, 3usize);
+
+        assert_eq!(orderbook.orders[1].logic.used, false);
+        assert_eq!(orderbook.orders[2].logic.used, false);
+        assert_eq!(orderbook.orders[3].logic.used, true);
+    } //}}}}}}
+
+    #[test]
+    fn match_result_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut test_order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.23),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut test_order.clone()).unwrap();
+        test_order.id = 2;
+        test_order.price = dec!(1.24);
+        orderbook.match_entry(&mut test_order.clone()).unwrap();
+        test_order.id = 3;
+        test_order.price = dec!(1.25);
+        orderbook.match_entry(&mut test_order.clone()).unwrap();
+
+        let mut taker = OrderInfo::new(
+            4,
+            10002,
+            OrderSide::Ask,
+            dec!(250),
+            dec!(1.24),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.match_entry(&mut taker).unwrap();
+
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].bid_order_id, 3);
+        assert_eq!(result.trades[0].trade_price, dec!(1.25));
+        assert_eq!(result.trades[1].bid_order_id, 2);
+        assert_eq!(result.trades[1].trade_price, dec!(1.24));
+        assert_eq!(result.taker_status, OrderStatus::PartTrade);
+        assert_eq!(result.filled_makers.len(), 2);
+        assert_eq!(result.filled_makers[0].id, 3);
+        assert_eq!(result.filled_makers[1].id, 2);
+        assert_eq!(result.rested.unwrap().remain_qty, dec!(50));
+        assert_eq!(orderbook.ask_leader.price, dec!(1.24));
+        assert_eq!(orderbook.bid_leader.price, dec!(1.23));
+
+        // market ask order consumes the whole bid side, the remain part is rejected
+        let mut taker = OrderInfo::new(
+            5,
+            10002,
+            OrderSide::Ask,
+            dec!(150),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        taker.op = OrderOp::Market;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].trade_qty, dec!(100));
+        assert_eq!(result.trades[1].trade_type, TradeType::RejectTrade);
+        assert_eq!(result.trades[1].ask_remain_qty, dec!(50));
+        assert_eq!(result.trades[1].trade_unfreeze_qty, dec!(50));
+        assert_eq!(result.trades[1].trade_unfreeze_type, QtyType::Base);
+        assert_eq!(result.taker_status, OrderStatus::AutoCancel);
+        assert_eq!(result.rejected_qty, dec!(50));
+        assert_eq!(result.rested, None);
+        assert!(orderbook.bid_price_index.is_empty());
+        assert_eq!(orderbook.bid_leader.qty, dec!(0));
+
+        // cancel the rested ask order
+        taker.id = 4;
+        taker.op = OrderOp::Cancel;
+        taker.price = dec!(1.24);
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.taker_status, OrderStatus::PartCancel);
+        assert!(orderbook.ask_price_index.is_empty());
+    } //}}}
+
+    #[test]
+    fn ioc_order_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut maker = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.23),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+
+        let mut taker = OrderInfo::new(
+            2,
+            10002,
+            OrderSide::Bid,
+            dec!(150),
+            dec!(1.23),
+            (dec!(0), dec!(0)),
+        );
+        taker.time_in_force = TimeInForce::IOC;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].trade_type, TradeType::SimpleTrade);
+        assert_eq!(result.trades[1].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.trades[1].bid_remain_qty, dec!(50));
+        assert_eq!(result.taker_status, OrderStatus::PartCancel);
+        assert_eq!(result.rested, None);
+        assert_eq!(result.rejected_qty, dec!(50));
+        assert!(orderbook.bid_price_index.is_empty());
+        assert!(orderbook.ask_price_index.is_empty());
+
+        // nothing can be matched, the whole IOC order is canceled
+        let mut taker = OrderInfo::new(
+            3,
+            10002,
+            OrderSide::Bid,
+            dec!(150),
+            dec!(1.23),
+            (dec!(0), dec!(0)),
+        );
+        taker.time_in_force = TimeInForce::IOC;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.taker_status, OrderStatus::AllCancel);
+        assert!(orderbook.bid_price_index.is_empty());
+    } //}}}
+
+    #[test]
+    fn fok_order_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut maker = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.23),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+        maker.id = 2;
+        maker.price = dec!(1.24);
+        orderbook.match_entry(&mut maker).unwrap();
+
+        // only 100 qty under the limit price 1.23, the order is rejected
+        let mut taker = OrderInfo::new(
+            3,
+            10002,
+            OrderSide::Bid,
+            dec!(150),
+            dec!(1.23),
+            (dec!(0), dec!(0)),
+        );
+        taker.time_in_force = TimeInForce::FOK;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.taker_status, OrderStatus::AllCancel);
+        assert_eq!(result.rejected_qty, dec!(150));
+        assert_eq!(orderbook.ask_price_index.len(), 2);
+        assert_eq!(orderbook.ask_leader.qty, dec!(100));
+        assert!(orderbook.bid_price_index.is_empty());
+
+        // 200 qty under the limit price 1.24, the order is filled entirely
+        let mut taker = OrderInfo::new(
+            4,
+            10002,
+            OrderSide::Bid,
+            dec!(150),
+            dec!(1.24),
+            (dec!(0), dec!(0)),
+        );
+        taker.time_in_force = TimeInForce::FOK;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.taker_status, OrderStatus::AllTrade);
+        assert_eq!(result.rejected_qty, dec!(0));
+        assert_eq!(orderbook.ask_leader.price, dec!(1.24));
+        assert_eq!(orderbook.ask_leader.qty, dec!(50));
+
+        // the hidden reserve of iceberg order is counted
+        let mut iceberg = OrderInfo::new(
+            5,
+            10001,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.25),
+            (dec!(0), dec!(0)),
+        );
+        iceberg.display_qty = dec!(10);
+        orderbook.match_entry(&mut iceberg).unwrap();
+        let mut taker = OrderInfo::new(
+            6,
+            10003,
+            OrderSide::Bid,
+            dec!(120),
+            dec!(1.25),
+            (dec!(0), dec!(0)),
+        );
+        taker.time_in_force = TimeInForce::FOK;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.taker_status, OrderStatus::AllTrade);
+        assert_eq!(result.rejected_qty, dec!(0));
+
+        // the own order is not liquidity, the order is rejected instead of partly filled
+        orderbook.set_self_trade_prevention(SelfTradePrevention::CancelNewest);
+        let mut maker = OrderInfo::new(
+            7,
+            10004,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.26),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+        let mut taker = OrderInfo::new(
+            8,
+            10004,
+            OrderSide::Bid,
+            dec!(50),
+            dec!(1.26),
+            (dec!(0), dec!(0)),
+        );
+        taker.time_in_force = TimeInForce::FOK;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.taker_status, OrderStatus::AllCancel);
+        assert_eq!(result.rejected_qty, dec!(50));
+    } //}}}
+
+    #[test]
+    fn post_only_order_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut maker = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.23),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+
+        // the crossed post only order is rejected
+        let mut order = OrderInfo::new(
+            2,
+            10002,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.25),
+            (dec!(0), dec!(0)),
+        );
+        order.post_only = true;
+        let result = orderbook.match_entry(&mut order.clone()).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.taker_status, OrderStatus::AllCancel);
+        assert!(orderbook.bid_price_index.is_empty());
+        assert_eq!(orderbook.ask_leader.qty, dec!(100));
+
+        // the crossed post only order is repriced one tick below the ask leader
+        orderbook.set_market_config(MarketConfig {
+            price_tick: dec!(0.01),
+            ..Default::default()
+        });
+        orderbook.set_post_only_mode(PostOnlyMode::Reprice);
+        let result = orderbook.match_entry(&mut order.clone()).unwrap();
+        assert_eq!(result.trades.len(), 0);
+        assert_eq!(result.rested.unwrap().price, dec!(1.22));
+        assert_eq!(orderbook.bid_leader.price, dec!(1.22));
+        assert_eq!(orderbook.ask_leader.qty, dec!(100));
+
+        // the post only order which does not cross rests as usual
+        order.id = 3;
+        order.price = dec!(1.2);
+        let result = orderbook.match_entry(&mut order.clone()).unwrap();
+        assert_eq!(result.rested.unwrap().price, dec!(1.2));
+        assert_eq!(orderbook.bid_price_index.len(), 2);
+    } //}}}
+
+    #[test]
+    fn stop_order_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut maker = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.25),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+        maker.id = 2;
+        maker.price = dec!(1.3);
+        orderbook.match_entry(&mut maker).unwrap();
+        maker.id = 3;
+        maker.side = OrderSide::Bid;
+        maker.price = dec!(1.2);
+        orderbook.match_entry(&mut maker).unwrap();
+
+        // buy stop limit order waits in the trigger index
+        let mut stop = OrderInfo::new(
+            4,
+            10002,
+            OrderSide::Bid,
+            dec!(50),
+            dec!(1.3),
+            (dec!(0), dec!(0)),
+        );
+        stop.op = OrderOp::StopLimit;
+        stop.trigger_price = dec!(1.25);
+        let result = orderbook.match_entry(&mut stop).unwrap();
+        assert_eq!(result.trades.len(), 0);
+        assert_eq!(result.taker_status, OrderStatus::PaddingTrade);
+        assert_eq!(orderbook.bid_stop_index.len(), 1);
+        assert_eq!(orderbook.bid_leader.price, dec!(1.2));
+
+        // sell stop market order waits in the trigger index
+        let mut stop = OrderInfo::new(
+            5,
+            10002,
+            OrderSide::Ask,
+            dec!(20),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        stop.op = OrderOp::StopMarket;
+        stop.trigger_price = dec!(1.1);
+        orderbook.match_entry(&mut stop).unwrap();
+        assert_eq!(orderbook.ask_stop_index.len(), 1);
+        assert_eq!(orderbook.stop_order_index.len(), 2);
+
+        // the stop order id can not be reused while it waits for trigger
+        let mut duplicate = stop;
+        duplicate.op = OrderOp::Limit;
+        duplicate.price = dec!(1.5);
+        assert_eq!(
+            orderbook.match_entry(&mut duplicate),
+            Err(TradeError::OrderIdDuplicate)
+        );
+
+        // the trade at 1.25 triggers the buy stop order
+        let mut taker = OrderInfo::new(
+            6,
+            10003,
+            OrderSide::Bid,
+            dec!(10),
+            dec!(1.25),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.triggered.len(), 1);
+        assert_eq!(result.triggered[0].order_id, 4);
+        assert_eq!(result.triggered[0].trades.len(), 1);
+        assert_eq!(result.triggered[0].trades[0].trade_price, dec!(1.25));
+        assert_eq!(result.triggered[0].taker_status, OrderStatus::AllTrade);
+        // the records of the taker come before the records of the triggered
+        let prices: Vec<Decimal> = result.records().map(|r| r.trade_price).collect();
+        assert_eq!(prices, vec![result.trades[0].trade_price, dec!(1.25)]);
+        assert!(orderbook.bid_stop_index.is_empty());
+        assert!(!orderbook.stop_order_index.contains_key(&4));
+        assert_eq!(orderbook.ask_leader.qty, dec!(40));
+
+        // cancel the sell stop order
+        stop.op = OrderOp::Cancel;
+        let result = orderbook.match_entry(&mut stop).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.taker_status, OrderStatus::AllCancel);
+        assert!(orderbook.ask_stop_index.is_empty());
+        assert!(orderbook.stop_order_index.is_empty());
+    } //}}}
+
+    #[test]
+    fn iceberg_order_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut iceberg = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        iceberg.display_qty = dec!(30);
+        let result = orderbook.match_entry(&mut iceberg).unwrap();
+        assert_eq!(result.rested.unwrap().remain_qty, dec!(30));
+        assert_eq!(result.rested.unwrap().hidden_qty, dec!(70));
+        assert_eq!(orderbook.bid_leader.qty, dec!(30));
+
+        let mut maker = OrderInfo::new(
+            2,
+            10002,
+            OrderSide::Bid,
+            dec!(50),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+        assert_eq!(orderbook.bid_leader.qty, dec!(80));
+
+        // the display slice is filled, the iceberg order is refilled and moves behind order 2
+        let mut taker = OrderInfo::new(
+            3,
+            10003,
+            OrderSide::Ask,
+            dec!(40),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].bid_order_id, 1);
+        assert_eq!(result.trades[0].trade_qty, dec!(30));
+        assert_eq!(result.trades[0].bid_remain_qty, dec!(70));
+        assert_eq!(result.trades[1].bid_order_id, 2);
+        assert_eq!(result.trades[1].trade_qty, dec!(10));
+        assert!(result.filled_makers.is_empty());
+
+        let node = orderbook.bid_price_index.get(&dec!(1.2)).unwrap();
+        assert_eq!(node.qty, dec!(70));
+        assert_eq!(node.order_slot, 2);
+        assert_eq!(node.last_slot, 1);
+        assert_eq!(orderbook.orders[1].remain_qty, dec!(30));
+        assert_eq!(orderbook.orders[1].hidden_qty, dec!(40));
+        assert_eq!(orderbook.orders[1].logic.pre_slot, 2);
+        assert_eq!(orderbook.orders[2].logic.next_slot, 1);
+
+        // cancel the iceberg order with its hidden reserve
+        iceberg.op = OrderOp::Cancel;
+        let result = orderbook.match_entry(&mut iceberg).unwrap();
+        assert_eq!(result.trades[0].bid_remain_qty, dec!(70));
+        assert_eq!(orderbook.bid_leader.qty, dec!(40));
+    } //}}}
+
+    #[test]
+    fn amend_order_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut order.clone()).unwrap();
+        order.id = 2;
+        orderbook.match_entry(&mut order.clone()).unwrap();
+        let mut maker = OrderInfo::new(
+            3,
+            10002,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.3),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+
+        // reduce qty at the same price keeps the queue position
+        let mut amend = order;
+        amend.id = 1;
+        amend.op = OrderOp::Amend;
+        amend.raw_qty = dec!(60);
+        let result = orderbook.match_entry(&mut amend).unwrap();
+        assert_eq!(result.trades.len(), 0);
+        assert_eq!(amend.remain_qty, dec!(60));
+        let node = orderbook.bid_price_index.get(&dec!(1.2)).unwrap();
+        assert_eq!(node.qty, dec!(160));
+        assert_eq!(node.order_slot, 1);
+        assert_eq!(node.last_slot, 2);
+        assert_eq!(orderbook.bid_leader.qty, dec!(160));
+
+        // increase qty re-queues the order at the tail
+        let mut amend = order;
+        amend.id = 1;
+        amend.op = OrderOp::Amend;
+        amend.raw_qty = dec!(120);
+        orderbook.match_entry(&mut amend).unwrap();
+        let node = orderbook.bid_price_index.get(&dec!(1.2)).unwrap();
+        assert_eq!(node.qty, dec!(220));
+        assert_eq!(node.order_slot, 2);
+        assert_eq!(orderbook.orders[node.last_slot].id, 1);
+
+        // the new price crosses the ask leader, the order is matched again
+        let mut amend = order;
+        amend.id = 1;
+        amend.op = OrderOp::Amend;
+        amend.price = dec!(1.3);
+        amend.raw_qty = dec!(120);
+        let result = orderbook.match_entry(&mut amend).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].trade_qty, dec!(100));
+        assert_eq!(result.rested.unwrap().remain_qty, dec!(20));
+        assert_eq!(orderbook.bid_leader.price, dec!(1.3));
+        assert_eq!(orderbook.bid_leader.qty, dec!(20));
+        assert!(orderbook.ask_price_index.is_empty());
+        assert_eq!(
+            orderbook.bid_price_index.get(&dec!(1.2)).unwrap().qty,
+            dec!(100)
+        );
+    } //}}}
+
+    #[test]
+    fn order_index_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut order.clone()).unwrap();
+        order.id = 2;
+        order.price = dec!(1.21);
+        orderbook.match_entry(&mut order.clone()).unwrap();
+        assert_eq!(orderbook.order_index.len(), 2);
+        assert_eq!(orderbook.order_index[&1], 1);
+        assert_eq!(orderbook.order_index[&2], 2);
+
+        // the filled maker order leaves the index
+        let mut taker = OrderInfo::new(
+            3,
+            10002,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(1.21),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(orderbook.order_index.len(), 1);
+        assert!(!orderbook.order_index.contains_key(&2));
+
+        // only the order owner can cancel the order
+        let mut cancel = OrderInfo::default();
+        cancel.id = 1;
+        cancel.uid = 10002;
+        cancel.op = OrderOp::Cancel;
+        assert_eq!(
+            orderbook.match_entry(&mut cancel),
+            Err(TradeError::OrderNotFound)
+        );
+        assert_eq!(orderbook.order_index.len(), 1);
+
+        // cancel by order id without side and price
+        cancel.uid = 10001;
+        let result = orderbook.match_entry(&mut cancel).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].bid_order_id, 1);
+        assert!(orderbook.order_index.is_empty());
+        assert!(orderbook.bid_price_index.is_empty());
+    } //}}}
+
+    #[test]
+    fn self_trade_prevention_test() {
+        //{{{
+        let new_book = |mode| {
+            let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+            orderbook.set_self_trade_prevention(mode);
+            let mut maker = OrderInfo::new(
+                1,
+                10001,
+                OrderSide::Ask,
+                dec!(100),
+                dec!(1.2),
+                (dec!(0), dec!(0)),
+            );
+            orderbook.match_entry(&mut maker).unwrap();
+            maker.id = 2;
+            maker.uid = 10002;
+            orderbook.match_entry(&mut maker).unwrap();
+            orderbook
+        };
+        let taker = OrderInfo::new(
+            3,
+            10001,
+            OrderSide::Bid,
+            dec!(150),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+
+        let mut orderbook = new_book(SelfTradePrevention::None);
+        let result = orderbook.match_entry(&mut taker.clone()).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].ask_uid, result.trades[0].bid_uid);
+
+        // the taker remain part is canceled, the maker order is untouched
+        let mut orderbook = new_book(SelfTradePrevention::CancelNewest);
+        let result = orderbook.match_entry(&mut taker.clone()).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.trades[0].bid_order_id, 3);
+        assert_eq!(result.taker_status, OrderStatus::AllCancel);
+        assert_eq!(orderbook.ask_leader.qty, dec!(200));
+        assert!(orderbook.bid_price_index.is_empty());
+
+        // the maker order is canceled, the taker goes on matching with order 2
+        let mut orderbook = new_book(SelfTradePrevention::CancelOldest);
+        let result = orderbook.match_entry(&mut taker.clone()).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.trades[0].ask_order_id, 1);
+        assert_eq!(result.trades[1].trade_type, TradeType::SimpleTrade);
+        assert_eq!(result.trades[1].ask_order_id, 2);
+        assert_eq!(result.rested.unwrap().remain_qty, dec!(50));
+        assert!(!orderbook.order_index.contains_key(&1));
+
+        let mut orderbook = new_book(SelfTradePrevention::CancelBoth);
+        let result = orderbook.match_entry(&mut taker.clone()).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].ask_order_id, 1);
+        assert_eq!(result.trades[1].bid_order_id, 3);
+        assert_eq!(orderbook.ask_leader.qty, dec!(100));
+        assert!(orderbook.bid_price_index.is_empty());
+
+        // the maker is smaller, it is canceled and the taker is decremented by 100
+        let mut orderbook = new_book(SelfTradePrevention::DecrementAndCancel);
+        let result = orderbook.match_entry(&mut taker.clone()).unwrap();
+        assert_eq!(result.trades.len(), 3);
+        assert_eq!(result.trades[0].ask_order_id, 1);
+        assert_eq!(result.trades[0].ask_remain_qty, dec!(100));
+        assert_eq!(result.trades[1].bid_order_id, 3);
+        assert_eq!(result.trades[1].bid_remain_qty, dec!(100));
+        assert_eq!(result.trades[2].trade_type, TradeType::SimpleTrade);
+        assert_eq!(result.trades[2].trade_qty, dec!(50));
+        assert_eq!(result.taker_status, OrderStatus::AllTrade);
+        assert_eq!(orderbook.ask_leader.qty, dec!(50));
+    } //}}}
+
+    #[test]
+    fn market_order_qty_type_test() {
+        //{{{
+        let new_book = || {
+            let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+            let mut maker = OrderInfo::new(
+                1,
+                10001,
+                OrderSide::Ask,
+                dec!(100),
+                dec!(2),
+                (dec!(0), dec!(0)),
+            );
+            orderbook.match_entry(&mut maker).unwrap();
+            maker.id = 2;
+            maker.price = dec!(4);
+            orderbook.match_entry(&mut maker).unwrap();
+            maker.id = 3;
+            maker.side = OrderSide::Bid;
+            maker.price = dec!(1);
+            orderbook.match_entry(&mut maker).unwrap();
+            orderbook
+        };
+
+        // market buy 150 base qty
+        let mut orderbook = new_book();
+        let mut taker = OrderInfo::new(
+            4,
+            10002,
+            OrderSide::Bid,
+            dec!(150),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        taker.op = OrderOp::Market;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(taker.trade_qty, dec!(150));
+        assert_eq!(taker.trade_oppo_qty, dec!(400));
+        assert_eq!(taker.avg_trade_price, dec!(400) / dec!(150));
+        assert_eq!(result.taker_status, OrderStatus::AllTrade);
+        assert_eq!(orderbook.ask_leader.qty, dec!(50));
+
+        // market buy with 300 quote funds
+        let mut orderbook = new_book();
+        let mut taker = OrderInfo::new(
+            4,
+            10002,
+            OrderSide::Bid,
+            dec!(300),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        taker.op = OrderOp::Market;
+        taker.qty_type = QtyType::Quote;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[1].trade_qty, dec!(25));
+        assert_eq!(taker.trade_qty, dec!(125));
+        assert_eq!(taker.trade_oppo_qty, dec!(300));
+        assert_eq!(result.taker_status, OrderStatus::AllTrade);
+        assert_eq!(orderbook.ask_leader.qty, dec!(75));
+
+        // market sell with 150 quote funds, the unspent funds should be unfreezed
+        let mut orderbook = new_book();
+        let mut taker = OrderInfo::new(
+            4,
+            10002,
+            OrderSide::Ask,
+            dec!(150),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        taker.op = OrderOp::Market;
+        taker.qty_type = QtyType::Quote;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].trade_qty, dec!(100));
+        assert_eq!(result.trades[0].trade_unfreeze_qty, dec!(0));
+        assert_eq!(result.trades[1].trade_type, TradeType::RejectTrade);
+        assert_eq!(result.trades[1].trade_unfreeze_qty, dec!(50));
+        assert_eq!(result.trades[1].trade_unfreeze_type, QtyType::Quote);
+        assert_eq!(result.rejected_qty, dec!(50));
+        assert!(orderbook.bid_price_index.is_empty());
+
+        // there is no maker order, the whole market order is rejected
+        let mut taker = OrderInfo::new(
+            5,
+            10002,
+            OrderSide::Ask,
+            dec!(10),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        taker.op = OrderOp::Market;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].trade_type, TradeType::RejectTrade);
+        assert_eq!(result.trades[0].ask_order_id, 5);
+        assert_eq!(result.trades[0].trade_unfreeze_qty, dec!(10));
+        assert_eq!(result.trades[0].trade_unfreeze_type, QtyType::Base);
+        assert_eq!(result.taker_status, OrderStatus::AutoCancel);
+    } //}}}
+
+    #[test]
+    fn trade_error_test() {
+        //{{{
+        // only one order can rest in the orderbook, slot 0 is never used
+        let mut orderbook = OrderBook::new(2, "BTC/USDT".to_owned());
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        assert_eq!(
+            orderbook.match_entry(&mut order.clone()),
+            Err(TradeError::OrderPriceIllegal)
+        );
+
+        order.price = dec!(1.2);
+        order.remain_qty = dec!(0);
+        assert_eq!(
+            orderbook.match_entry(&mut order.clone()),
+            Err(TradeError::OrderQtyIllegal)
+        );
+
+        order.remain_qty = dec!(100);
+        orderbook.match_entry(&mut order.clone()).unwrap();
+        assert_eq!(
+            orderbook.match_entry(&mut order.clone()),
+            Err(TradeError::OrderIdDuplicate)
+        );
+
+        // the remain part can not rest in the full orderbook
+        order.id = 2;
+        let result = orderbook.match_entry(&mut order.clone()).unwrap();
+        assert_eq!(result.rejected_qty, dec!(100));
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+
+        // the illegal order does not touch the orderbook
+        assert_eq!(orderbook.order_index.len(), 1);
+        assert_eq!(orderbook.bid_leader.qty, dec!(100));
+
+        let mut amend = order;
+        amend.op = OrderOp::Amend;
+        assert_eq!(
+            orderbook.match_entry(&mut amend.clone()),
+            Err(TradeError::OrderNotFound)
+        );
+        amend.id = 1;
+        amend.side = OrderSide::Ask;
+        assert_eq!(
+            orderbook.match_entry(&mut amend.clone()),
+            Err(TradeError::OrderSideIllegal)
+        );
+
+        let mut cancel = amend;
+        cancel.op = OrderOp::Cancel;
+        cancel.id = 3;
+        assert_eq!(
+            orderbook.match_entry(&mut cancel),
+            Err(TradeError::OrderNotFound)
+        );
+        assert_eq!(orderbook.bid_leader.qty, dec!(100));
+
+        // the order fully filled in the full orderbook needs no slot
+        let mut taker = order;
+        taker.id = 4;
+        taker.uid = 10002;
+        taker.side = OrderSide::Ask;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.taker_status, OrderStatus::AllTrade);
+        assert_eq!(result.rejected_qty, dec!(0));
+        assert!(orderbook.bid_leader.qty.is_zero());
+    } //}}}
+
+    #[test]
+    fn trade_overflow_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let price = dec!(10000000000000000000000000000);
+        let mut maker = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(1000),
+            price,
+            (dec!(0), dec!(0)),
+        );
+        assert_eq!(
+            orderbook.match_entry(&mut maker.clone()),
+            Err(TradeError::OrderNotionalIllegal)
+        );
+
+        maker.raw_qty = dec!(5);
+        maker.remain_qty = dec!(5);
+        orderbook.match_entry(&mut maker.clone()).unwrap();
+        maker.id = 2;
+        orderbook.match_entry(&mut maker.clone()).unwrap();
+
+        // the second trade overflows the quote qty of the taker, the first one is kept
+        // and the remain part is rejected without touching the second maker
+        let mut taker = OrderInfo::new(
+            3,
+            10002,
+            OrderSide::Ask,
+            dec!(10),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        taker.op = OrderOp::Market;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].trade_qty, dec!(5));
+        assert_eq!(result.trades[1].trade_type, TradeType::RejectTrade);
+        assert_eq!(result.rejected_qty, dec!(5));
+        assert_eq!(result.taker_status, OrderStatus::AutoCancel);
+        assert_eq!(orderbook.bid_leader.qty, dec!(5));
+        assert_eq!(
+            orderbook.orders[orderbook.order_index[&2]].remain_qty,
+            dec!(5)
+        );
+
+        // the remain part of limit order does not rest in the crossed orderbook
+        maker.id = 4;
+        orderbook.match_entry(&mut maker.clone()).unwrap();
+        let mut taker = OrderInfo::new(
+            5,
+            10002,
+            OrderSide::Ask,
+            dec!(10),
+            dec!(1),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.rejected_qty, dec!(5));
+        assert!(result.rested.is_none());
+        assert!(orderbook.ask_leader.qty.is_zero());
+        assert_eq!(orderbook.bid_leader.qty, dec!(5));
+
+        // the order which would overflow the qty of the price node can not rest
+        let qty = dec!(50000000000000000000000000000);
+        let mut maker = OrderInfo::new(6, 10001, OrderSide::Bid, qty, dec!(1), (dec!(0), dec!(0)));
+        orderbook.match_entry(&mut maker.clone()).unwrap();
+        maker.id = 7;
+        let result = orderbook.match_entry(&mut maker).unwrap();
+        assert_eq!(result.trades[0].trade_type, TradeType::CancelTrade);
+        assert_eq!(result.rejected_qty, qty);
+        assert_eq!(orderbook.bid_price_index[&dec!(1)].qty, qty);
+    } //}}}
+
+    #[test]
+    fn market_config_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        orderbook.set_market_config(MarketConfig {
+            base_asset: "BTC".to_owned(),
+            quote_asset: "USDT".to_owned(),
+            price_tick: dec!(0.01),
+            qty_step: dec!(0.1),
+            min_qty: dec!(1),
+            max_price: dec!(100000),
+            max_qty: dec!(1000),
+            min_notional: dec!(10),
+            price_scale: 2,
+            qty_scale: 1,
+        });
+        let order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+
+        // the price is off the price tick or above the max price
+        for price in [dec!(1.2345678901), dec!(100000.01)].iter() {
+            let mut illegal = order;
+            illegal.price = *price;
+            assert_eq!(
+                orderbook.match_entry(&mut illegal),
+                Err(TradeError::OrderPriceIllegal)
+            );
+        }
+
+        // the qty is off the qty step or out of the qty range
+        for qty in [dec!(10.05), dec!(0.5), dec!(1000.1)].iter() {
+            let mut illegal = order;
+            illegal.raw_qty = *qty;
+            illegal.remain_qty = *qty;
+            assert_eq!(
+                orderbook.match_entry(&mut illegal),
+                Err(TradeError::OrderQtyIllegal)
+            );
+        }
+
+        // the display qty of iceberg order is off the qty step or less than the min qty
+        for qty in [dec!(0.3), dec!(10.05), dec!(0.5)].iter() {
+            let mut illegal = order;
+            illegal.display_qty = *qty;
+            assert_eq!(
+                orderbook.match_entry(&mut illegal),
+                Err(TradeError::OrderQtyIllegal)
+            );
+        }
+
+        // the order value is less than the min notional
+        let mut illegal = order;
+        illegal.raw_qty = dec!(5);
+        illegal.remain_qty = dec!(5);
+        assert_eq!(
+            orderbook.match_entry(&mut illegal),
+            Err(TradeError::OrderNotionalIllegal)
+        );
+        let mut illegal = order;
+        illegal.op = OrderOp::Market;
+        illegal.qty_type = QtyType::Quote;
+        illegal.raw_qty = dec!(9);
+        illegal.remain_qty = dec!(9);
+        assert_eq!(
+            orderbook.match_entry(&mut illegal),
+            Err(TradeError::OrderNotionalIllegal)
+        );
+        assert!(orderbook.bid_price_index.is_empty());
+
+        // trailing zeros are not counted in the scale
+        let mut legal = order;
+        legal.price = dec!(1.200);
+        orderbook.match_entry(&mut legal).unwrap();
+        assert_eq!(orderbook.bid_leader.qty, dec!(100));
+
+        let mut amend = legal;
+        amend.op = OrderOp::Amend;
+        amend.price = dec!(1.215);
+        assert_eq!(
+            orderbook.match_entry(&mut amend),
+            Err(TradeError::OrderPriceIllegal)
+        );
+
+        // the reserve of iceberg order left less than the min qty is shown with the last slice
+        let mut iceberg = order;
+        iceberg.id = 2;
+        iceberg.side = OrderSide::Ask;
+        iceberg.price = dec!(2);
+        iceberg.raw_qty = dec!(20.5);
+        iceberg.remain_qty = dec!(20.5);
+        iceberg.display_qty = dec!(10);
+        let result = orderbook.match_entry(&mut iceberg).unwrap();
+        assert_eq!(result.rested.unwrap().remain_qty, dec!(10));
+        assert_eq!(result.rested.unwrap().hidden_qty, dec!(10.5));
+        let mut taker = order;
+        taker.id = 3;
+        taker.uid = 10002;
+        taker.price = dec!(2);
+        taker.raw_qty = dec!(10);
+        taker.remain_qty = dec!(10);
+        orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(orderbook.ask_leader.qty, dec!(10.5));
+    } //}}}
+
+    #[test]
+    fn lot_size_test() {
+        //{{{
+        // the qty bought by the funds is rounded down to the qty step,
+        // the funds left are less than one step and rejected
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        orderbook.set_market_config(MarketConfig {
+            qty_step: dec!(0.01),
+            ..Default::default()
+        });
+        let mut maker = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Ask,
+            dec!(100),
+            dec!(7),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut maker).unwrap();
+        let mut taker = OrderInfo::new(
+            2,
+            10002,
+            OrderSide::Bid,
+            dec!(200),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        taker.op = OrderOp::Market;
+        taker.qty_type = QtyType::Quote;
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.trades[0].trade_qty, dec!(28.57));
+        assert_eq!(result.trades[0].trade_oppo_qty, dec!(199.99));
+        assert_eq!(result.trades[1].trade_type, TradeType::RejectTrade);
+        assert_eq!(result.trades[1].trade_unfreeze_qty, dec!(0.01));
+        assert_eq!(orderbook.ask_leader.qty, dec!(71.43));
+    } //}}}
+
+    #[test]
+    fn mass_cancel_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut id = 0;
+        for uid in [10001u64, 10002].iter() {
+            for (side, price) in [
+                (OrderSide::Bid, dec!(1.1)),
+                (OrderSide::Bid, dec!(1.2)),
+                (OrderSide::Ask, dec!(1.3)),
+                (OrderSide::Ask, dec!(1.4)),
+            ]
+            .iter()
+            {
+                id += 1;
+                let mut order =
+                    OrderInfo::new(id, *uid, *side, dec!(100), *price, (dec!(0), dec!(0)));
+                orderbook.match_entry(&mut order).unwrap();
+            }
+        }
+        let mut stop = OrderInfo::new(
+            100,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.5),
+            (dec!(0), dec!(0)),
+        );
+        stop.op = OrderOp::StopLimit;
+        stop.trigger_price = dec!(1.5);
+        orderbook.match_entry(&mut stop).unwrap();
+
+        // all orders of the uid, including the stop order
+        let result = orderbook.mass_cancel(MassCancel::User(10001));
+        assert_eq!(result.trades.len(), 5);
+        assert!(result
+            .trades
+            .iter()
+            .all(|t| t.trade_type == TradeType::CancelTrade));
+        assert!(orderbook.bid_stop_index.is_empty());
+        assert!(orderbook.stop_order_index.is_empty());
+        assert_eq!(orderbook.order_index.len(), 4);
+        assert_eq!(orderbook.bid_leader.price, dec!(1.2));
+        assert_eq!(orderbook.bid_leader.qty, dec!(100));
+
+        // the bids priced in the band
+        let result =
+            orderbook.mass_cancel(MassCancel::PriceBand(OrderSide::Bid, dec!(1.15), dec!(1.3)));
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].bid_order_id, 6);
+        assert_eq!(orderbook.bid_leader.price, dec!(1.1));
+
+        // all asks
+        let result = orderbook.mass_cancel(MassCancel::Side(OrderSide::Ask));
+        assert_eq!(result.trades.len(), 2);
+        assert!(orderbook.ask_price_index.is_empty());
+        assert_eq!(orderbook.ask_leader.qty, dec!(0));
+        assert_eq!(orderbook.bid_leader.qty, dec!(100));
+    } //}}}
+
+    #[test]
+    fn depth_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        for (id, side, price) in [
+            (1, OrderSide::Bid, dec!(1.1)),
+            (2, OrderSide::Bid, dec!(1.2)),
+            (3, OrderSide::Bid, dec!(1.2)),
+            (4, OrderSide::Bid, dec!(1.0)),
+            (5, OrderSide::Ask, dec!(1.3)),
+        ]
+        .iter()
+        {
+            let mut order =
+                OrderInfo::new(*id, 10001, *side, dec!(100), *price, (dec!(0), dec!(0)));
+            let result = orderbook.match_entry(&mut order).unwrap();
+            assert_eq!(result.depth_updates.len(), 1);
+        }
+
+        let depth = orderbook.depth(2);
+        assert_eq!(
+            depth.bids,
+            vec![
+                PriceLevel {
+                    price: dec!(1.2),
+                    qty: dec!(200)
+                },
+                PriceLevel {
+                    price: dec!(1.1),
+                    qty: dec!(100)
+                },
+            ]
+        );
+        assert_eq!(depth.asks.len(), 1);
+
+        // the taker clears the level 1.2, reduces the level 1.1 and rests at 1.05
+        orderbook.seq = 7;
+        let mut taker = OrderInfo::new(
+            6,
+            10002,
+            OrderSide::Ask,
+            dec!(350),
+            dec!(1.05),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        let update = |side, price, qty| DepthUpdate {
+            seq: 7,
+            side: side,
+            price: price,
+            qty: qty,
+        };
+        assert_eq!(
+            result.depth_updates,
+            vec![
+                update(OrderSide::Bid, dec!(1.1), dec!(0)),
+                update(OrderSide::Bid, dec!(1.2), dec!(0)),
+                update(OrderSide::Ask, dec!(1.05), dec!(50)),
+            ]
+        );
+
+        let result = orderbook.mass_cancel(MassCancel::Side(OrderSide::Ask));
+        assert_eq!(result.depth_updates.len(), 2);
+        assert!(result.depth_updates.iter().all(|u| u.qty.is_zero()));
+        assert!(orderbook.depth(10).asks.is_empty());
+
+        // the handled message is numbered after the last one
+        let mut order = OrderInfo::new(
+            7,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.3),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.handle(Msg::SimpleOrder(order)).unwrap().unwrap();
+        assert_eq!(orderbook.seq(), 8);
+        assert_eq!(result.depth_updates[0].seq, 8);
+        order.id = 8;
+        let result = orderbook.handle(Msg::SimpleOrder(order)).unwrap().unwrap();
+        assert_eq!(result.depth_updates[0].seq, 9);
+    } //}}}
+
+    #[test]
+    fn order_event_test() {
+        //{{{
+        // the l3 events are off by default
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.match_entry(&mut order).unwrap();
+        assert!(result.order_events.is_empty());
+
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        orderbook.set_order_events(true);
+        for id in 1..4 {
+            let mut order = OrderInfo::new(
+                id,
+                10001,
+                OrderSide::Bid,
+                dec!(100),
+                dec!(1.2),
+                (dec!(0), dec!(0)),
+            );
+            let result = orderbook.match_entry(&mut order).unwrap();
+            assert_eq!(result.order_events.len(), 1);
+            let event = result.order_events[0];
+            assert_eq!(event.kind, OrderEventKind::Add);
+            assert_eq!(event.position, id as usize - 1);
+        }
+
+        // cancel the middle order, the tail moves forward
+        let mut cancel = OrderInfo::new(
+            2,
+            10001,
+            OrderSide::Bid,
+            dec!(0),
+            dec!(0),
+            (dec!(0), dec!(0)),
+        );
+        cancel.op = OrderOp::Cancel;
+        let result = orderbook.match_entry(&mut cancel).unwrap();
+        assert_eq!(result.order_events.len(), 1);
+        assert_eq!(result.order_events[0].kind, OrderEventKind::Delete);
+        assert_eq!(result.order_events[0].position, 1);
+        assert_eq!(result.order_events[0].qty, dec!(100));
+        let slot = orderbook.order_index[&3];
+        assert_eq!(orderbook.queue_position(slot), 1);
+
+        // reduce the tail order in place
+        let mut amend = OrderInfo::new(
+            3,
+            10001,
+            OrderSide::Bid,
+            dec!(60),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        amend.op = OrderOp::Amend;
+        let result = orderbook.match_entry(&mut amend).unwrap();
+        assert_eq!(result.order_events[0].kind, OrderEventKind::Reduce);
+        assert_eq!(result.order_events[0].qty, dec!(40));
+        assert_eq!(result.order_events[0].remain_qty, dec!(60));
+
+        // the taker fills the head order and trades part of the tail one
+        orderbook.seq = 9;
+        let mut taker = OrderInfo::new(
+            4,
+            10002,
+            OrderSide::Ask,
+            dec!(120),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        let result = orderbook.match_entry(&mut taker).unwrap();
+        let events: Vec<_> = result
+            .order_events
+            .iter()
+            .map(|e| (e.seq, e.kind, e.order_id, e.qty, e.remain_qty, e.position))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (9, OrderEventKind::Execute, 1, dec!(100), dec!(0), 0),
+                (9, OrderEventKind::Execute, 3, dec!(20), dec!(40), 0),
+            ]
+        );
+    } //}}}
+
+    #[test]
+    fn trade_id_test() {
+        //{{{
+        let clock = clock::ManualClock::new(1000);
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        orderbook.set_clock(Box::new(clock.clone()));
+
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.handle(Msg::SimpleOrder(order));
+        order.id = 2;
+        orderbook.handle(Msg::SimpleOrder(order));
+
+        clock.advance(500);
+        order.id = 3;
+        order.uid = 10002;
+        order.side = OrderSide::Ask;
+        order.raw_qty = dec!(150);
+        order.remain_qty = dec!(150);
+        let result = orderbook.handle(Msg::SimpleOrder(order)).unwrap().unwrap();
+        let ids: Vec<_> = result.trades.iter().map(|r| r.trade_id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(result.trades.iter().all(|r| r.time_stamp == 1500));
+
+        // the cancel records are numbered too
+        clock.set(3000);
+        let result = orderbook
+            .handle(Msg::CancelOrder((2, 10001)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.trades[0].trade_id, 3);
+        assert_eq!(result.trades[0].time_stamp, 3000);
+        assert_eq!(orderbook.trade_seq(), 3);
+
+        // the journal time is used instead of the clock
+        order.id = 4;
+        let result = orderbook
+            .apply_at(9, 7000, Msg::SimpleOrder(order))
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.trades.len(), 0);
+        let result = orderbook
+            .apply_at(10, 8000, Msg::CancelAllOrder)
+            .unwrap()
+            .unwrap();
+        assert_eq!(result.trades[0].trade_id, 4);
+        assert_eq!(result.trades[0].time_stamp, 8000);
+    } //}}}
+
+    #[test]
+    fn run_test() {
+        //{{{
+        let (sender, recv) = unbounded();
+        let (signal_sender, signal) = unbounded();
+        let (output, results) = unbounded();
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let dir = std::env::temp_dir().join(format!("run_test_{}", std::process::id()));
+        orderbook.set_snapshot_mode(SnapshotMode::Blocking, dir.to_str().unwrap());
+        let handle = orderbook.run(recv, signal, output, None);
+
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        sender.send(Msg::SimpleOrder(order)).unwrap();
+        order.id = 2;
+        sender.send(Msg::SimpleOrder(order)).unwrap();
+        order.id = 3;
+        order.op = OrderOp::StopLimit;
+        order.trigger_price = dec!(1.3);
+        sender.send(Msg::SimpleOrder(order)).unwrap();
+        sender.send(Msg::CancelOrder((1, 10001))).unwrap();
+
+        for _ in 0..3 {
+            results.recv().unwrap().unwrap();
+        }
+        let result = results.recv().unwrap().unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].bid_order_id, 1);
+
+        // both the resting order and the stop order are canceled
+        sender.send(Msg::CancelAllOrder).unwrap();
+        let result = results.recv().unwrap().unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert!(result
+            .trades
+            .iter()
+            .all(|t| t.trade_type == TradeType::CancelTrade));
+
+        // the snapshot failure is reported, the orderbook keeps running
+        sender.send(Msg::Snapshot).unwrap();
+        assert_eq!(results.recv().unwrap(), Err(TradeError::SnapshotFailed));
+
+        signal_sender.send(Signal::Closed).unwrap();
+        let orderbook = handle.join().unwrap();
+        assert!(orderbook.order_index.is_empty());
+        assert!(orderbook.bid_stop_index.is_empty());
+        assert_eq!(orderbook.bid_leader.qty, dec!(0));
+    } //}}}
+
+    #[test]
+    fn fork_snapshot_test() {
+        //{{{
+        let dir = std::env::temp_dir().join(format!("fork_snapshot_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        orderbook.set_snapshot_mode(SnapshotMode::Fork, dir.to_str().unwrap());
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.apply(1, Msg::SimpleOrder(order));
+        orderbook.apply(2, Msg::Snapshot);
+
+        // the parent keeps matching while the child writes the snapshot
+        order.id = 2;
+        orderbook.apply(3, Msg::SimpleOrder(order));
+        let result = orderbook.wait_snapshot().unwrap();
+        assert!(result.success());
+        assert_eq!(result.seq, 2);
+        assert_eq!(orderbook.last_snapshot(), Some(result));
+        assert!(orderbook.wait_snapshot().is_none());
+
+        // the snapshot is the orderbook at the fork
+        let path = std::fs::read_dir(&dir)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mut file = File::open(path).unwrap();
+        let snapshot = snapshot::read(&mut file).unwrap();
+        assert_eq!(snapshot.seq(), 2);
+        assert_eq!(snapshot.order_index.len(), 1);
+        assert_eq!(orderbook.order_index.len(), 2);
+
+        // the child fails to write into the missing dir
+        let _ = std::fs::remove_dir_all(&dir);
+        orderbook.apply(4, Msg::Snapshot);
+        let result = orderbook.wait_snapshot().unwrap();
+        assert!(!result.success());
+        assert_eq!(result.seq, 4);
+    } //}}}
+
+    #[test]
+    fn snapshot_test() {
+        let mut orderbook = OrderBook::new(2, "BTC_USDT".to_owned());
+        let mut test_order = OrderInfo::default();
+        test_order.id = 1;
+        test_order.price = dec!(1.23);
+        test_order.raw_qty = dec!(100);
+        test_order.remain_qty = dec!(100);
+        test_order.uid = 10001;
+        orderbook.insert_order(&mut test_order.clone()).unwrap();
+        orderbook.snapshot().unwrap();
+
+        // the snapshot dir does not exist
+        let dir = std::env::temp_dir().join(format!("snapshot_test_{}", std::process::id()));
+        orderbook.set_snapshot_mode(SnapshotMode::Blocking, dir.to_str().unwrap());
+        assert!(orderbook.snapshot().is_err());
+
+        // the file is named by the date of the clock
+        fs::create_dir_all(&dir).unwrap();
+        orderbook.set_clock(Box::new(clock::ManualClock::new(1_000_000_000_000)));
+        orderbook.snapshot().unwrap();
+        assert!(dir.join("2001-09-09_BTC%5FUSDT.d").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
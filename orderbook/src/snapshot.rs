@@ -0,0 +1,587 @@
+use crate::{MarketConfig, OrderBook, PostOnlyMode, SelfTradePrevention, SnapshotMode};
+use common::crc32::Crc32;
+use order::proto::{OrderInfo, OrderLogic, OrderOp, OrderSide, OrderStatus, QtyType, TimeInForce};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use std::fmt;
+use std::io::{self, prelude::*};
+
+pub const MAGIC: [u8; 4] = *b"MESN"; // match engine snapshot
+pub const FORMAT_VERSION: u16 = 1; // the newest format version this build can read and write
+const TRAILER_LEN: usize = 12; // body len u64 + body crc32 u32
+
+// the enums are written as their index in these tables, append only
+const ORDER_OPS: [OrderOp; 6] = [
+    OrderOp::Limit,
+    OrderOp::Market,
+    OrderOp::Cancel,
+    OrderOp::StopMarket,
+    OrderOp::StopLimit,
+    OrderOp::Amend,
+];
+const TIME_IN_FORCES: [TimeInForce; 3] = [TimeInForce::GTC, TimeInForce::IOC, TimeInForce::FOK];
+const QTY_TYPES: [QtyType; 2] = [QtyType::Base, QtyType::Quote];
+const ORDER_SIDES: [OrderSide; 2] = [OrderSide::Bid, OrderSide::Ask];
+const ORDER_STATUSES: [OrderStatus; 6] = [
+    OrderStatus::PaddingTrade,
+    OrderStatus::AllTrade,
+    OrderStatus::PartTrade,
+    OrderStatus::AllCancel,
+    OrderStatus::PartCancel,
+    OrderStatus::AutoCancel,
+];
+const POST_ONLY_MODES: [PostOnlyMode; 2] = [PostOnlyMode::Reject, PostOnlyMode::Reprice];
+const STP_MODES: [SelfTradePrevention; 5] = [
+    SelfTradePrevention::None,
+    SelfTradePrevention::CancelNewest,
+    SelfTradePrevention::CancelOldest,
+    SelfTradePrevention::CancelBoth,
+    SelfTradePrevention::DecrementAndCancel,
+];
+const SNAPSHOT_MODES: [SnapshotMode; 2] = [SnapshotMode::Blocking, SnapshotMode::Fork];
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),           // reading or writing the snapshot failed
+    BadMagic,                // the file is not a snapshot
+    UnsupportedVersion(u16), // the snapshot is written by a newer format version
+    Corrupt(String),         // the checksum or the content of the snapshot is broken
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "io error: {}", err),
+            SnapshotError::BadMagic => write!(f, "bad snapshot magic"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Corrupt(msg) => write!(f, "snapshot corrupt: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+/// The snapshot file header
+/// magic | version u16 | market len u16 | market | seq u64 | header crc32 u32,
+/// the header crc covers the header bytes before it. the body follows the header and ends with
+/// the trailer body len u64 | body crc32 u32. the integers are little endian
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SnapshotHeader {
+    pub version: u16,   // format version
+    pub market: String, // market of the orderbook
+    pub seq: u64,       // journal sequence number of the last handled message
+}
+
+// the stream which checksums the bytes passing through it
+struct CrcStream<'a, S> {
+    inner: &'a mut S,
+    crc: Crc32,
+    len: u64,
+}
+
+impl<'a, S> CrcStream<'a, S> {
+    fn new(inner: &'a mut S) -> CrcStream<'a, S> {
+        CrcStream {
+            inner: inner,
+            crc: Crc32::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<'a, W: Write> Write for CrcStream<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a, R: Read> Read for CrcStream<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+}
+
+/// stream the live orders, the price levels and the settings of the orderbook into the writer,
+/// the settings include the l3 order events switch, the snapshot mode and dir.
+/// the empty order slots are not written
+pub fn write<W: Write>(orderbook: &OrderBook, w: &mut W) -> Result<SnapshotHeader, SnapshotError> {
+    //{{{
+    let header = SnapshotHeader {
+        version: FORMAT_VERSION,
+        market: orderbook.market.clone(),
+        seq: orderbook.seq,
+    };
+    let mut head = CrcStream::new(w);
+    write_header(&mut head, &header)?;
+    let header_crc = head.crc.finish();
+    put_u32(w, header_crc)?;
+
+    let mut body = CrcStream::new(w);
+    put_u64(&mut body, orderbook.orders.len() as u64)?;
+    put_config(&mut body, &orderbook.config)?;
+    put_u8(&mut body, orderbook.post_only_mode as u8)?;
+    put_u8(&mut body, orderbook.order_events_enabled as u8)?;
+    put_u8(&mut body, orderbook.stp_mode as u8)?;
+    put_u8(&mut body, orderbook.snapshot_mode as u8)?;
+    put_str(&mut body, &orderbook.snapshot_dir)?;
+    put_decimal(&mut body, orderbook.last_price)?;
+    put_u64(&mut body, orderbook.trade_seq)?;
+
+    for index in [&orderbook.bid_price_index, &orderbook.ask_price_index].iter() {
+        put_u64(&mut body, index.len() as u64)?;
+        for (price, node) in index.iter() {
+            let mut slots = Vec::new();
+            let mut slot = node.order_slot;
+            while slot != 0 {
+                slots.push(slot);
+                slot = orderbook.orders[slot].logic.next_slot;
+            }
+
+            put_decimal(&mut body, *price)?;
+            put_decimal(&mut body, node.qty)?;
+            put_u64(&mut body, slots.len() as u64)?;
+            for slot in slots {
+                put_order(&mut body, &orderbook.orders[slot])?;
+            }
+        }
+    }
+
+    let stop_num: usize = orderbook
+        .bid_stop_index
+        .values()
+        .chain(orderbook.ask_stop_index.values())
+        .map(|orders| orders.len())
+        .sum();
+    put_u64(&mut body, stop_num as u64)?;
+    for orders in orderbook
+        .bid_stop_index
+        .values()
+        .chain(orderbook.ask_stop_index.values())
+    {
+        for order in orders.iter() {
+            put_order(&mut body, order)?;
+        }
+    }
+
+    let body_len = body.len;
+    let body_crc = body.crc.finish();
+    put_u64(w, body_len)?;
+    put_u32(w, body_crc)?;
+    w.flush()?;
+    Ok(header)
+} //}}}
+
+/// read and check the snapshot header
+pub fn read_header<R: Read>(r: &mut R) -> Result<SnapshotHeader, SnapshotError> {
+    //{{{
+    let mut head = CrcStream::new(r);
+    let mut magic = [0u8; 4];
+    head.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = get_u16(&mut head)?;
+    if version > FORMAT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let market_len = get_u16(&mut head)? as usize;
+    let mut market = vec![0u8; market_len];
+    head.read_exact(&mut market)?;
+    let seq = get_u64(&mut head)?;
+    let header_crc = head.crc.finish();
+    if get_u32(r)? != header_crc {
+        return Err(SnapshotError::Corrupt(
+            "header checksum mismatch".to_owned(),
+        ));
+    }
+    let market = String::from_utf8(market)
+        .map_err(|_| SnapshotError::Corrupt("market is not utf8".to_owned()))?;
+
+    Ok(SnapshotHeader {
+        version: version,
+        market: market,
+        seq: seq,
+    })
+} //}}}
+
+/// read the snapshot and rebuild the orderbook, the body is checked before it is decoded
+pub fn read<R: Read>(r: &mut R) -> Result<OrderBook, SnapshotError> {
+    //{{{
+    let header = read_header(r)?;
+    let mut body = Vec::new();
+    r.read_to_end(&mut body)?;
+    if body.len() < TRAILER_LEN {
+        return Err(SnapshotError::Corrupt("body is truncated".to_owned()));
+    }
+    let trailer = body.split_off(body.len() - TRAILER_LEN);
+    let trailer = &mut &trailer[..];
+    if get_u64(trailer)? != body.len() as u64 {
+        return Err(SnapshotError::Corrupt("body is truncated".to_owned()));
+    }
+    let mut crc = Crc32::new();
+    crc.update(&body);
+    if crc.finish() != get_u32(trailer)? {
+        return Err(SnapshotError::Corrupt("body checksum mismatch".to_owned()));
+    }
+
+    let r = &mut &body[..];
+    let max_order_num = get_u64(r)? as usize;
+    let mut orderbook = OrderBook::new(max_order_num, header.market);
+    orderbook.seq = header.seq;
+    orderbook.config = get_config(r)?;
+    orderbook.post_only_mode = get_enum(r, &POST_ONLY_MODES)?;
+    orderbook.order_events_enabled = get_enum(r, &[false, true])?;
+    orderbook.stp_mode = get_enum(r, &STP_MODES)?;
+    orderbook.snapshot_mode = get_enum(r, &SNAPSHOT_MODES)?;
+    orderbook.snapshot_dir = get_str(r)?;
+    orderbook.last_price = get_decimal(r)?;
+    orderbook.trade_seq = get_u64(r)?;
+
+    for side in ORDER_SIDES.iter() {
+        let level_num = get_u64(r)?;
+        for _ in 0..level_num {
+            let price = get_decimal(r)?;
+            let qty = get_decimal(r)?;
+            let order_num = get_u64(r)?;
+
+            // the orders are inserted in their queue order
+            let mut level_qty = dec!(0);
+            for _ in 0..order_num {
+                let mut order = get_order(r)?;
+                if order.side != *side || order.price != price {
+                    return Err(SnapshotError::Corrupt(format!(
+                        "order {} is not in its price level",
+                        order.id
+                    )));
+                }
+                level_qty += order.remain_qty;
+                orderbook
+                    .insert_order(&mut order)
+                    .map_err(|err| SnapshotError::Corrupt(err.to_string()))?;
+            }
+            if level_qty != qty {
+                return Err(SnapshotError::Corrupt(format!(
+                    "price level {} qty mismatch",
+                    price
+                )));
+            }
+        }
+    }
+
+    let stop_num = get_u64(r)?;
+    for _ in 0..stop_num {
+        let mut order = get_order(r)?;
+        orderbook.insert_stop_order(&mut order);
+    }
+
+    if !r.is_empty() {
+        return Err(SnapshotError::Corrupt("trailing bytes in body".to_owned()));
+    }
+    // the rebuilt levels are not the depth updates or the order events
+    orderbook.depth_touched.clear();
+    orderbook.order_events.clear();
+    Ok(orderbook)
+} //}}}
+
+fn write_header<W: Write>(w: &mut W, header: &SnapshotHeader) -> Result<(), SnapshotError> {
+    //{{{
+    w.write_all(&MAGIC)?;
+    put_u16(w, header.version)?;
+    put_str(w, &header.market)?;
+    put_u64(w, header.seq)?;
+    Ok(())
+} //}}}
+
+fn put_config<W: Write>(w: &mut W, config: &MarketConfig) -> Result<(), SnapshotError> {
+    //{{{
+    put_str(w, &config.base_asset)?;
+    put_str(w, &config.quote_asset)?;
+    put_decimal(w, config.price_tick)?;
+    put_decimal(w, config.max_price)?;
+    put_decimal(w, config.qty_step)?;
+    put_decimal(w, config.min_qty)?;
+    put_decimal(w, config.max_qty)?;
+    put_decimal(w, config.min_notional)?;
+    put_u32(w, config.price_scale)?;
+    put_u32(w, config.qty_scale)?;
+    Ok(())
+} //}}}
+
+fn get_config<R: Read>(r: &mut R) -> Result<MarketConfig, SnapshotError> {
+    //{{{
+    Ok(MarketConfig {
+        base_asset: get_str(r)?,
+        quote_asset: get_str(r)?,
+        price_tick: get_decimal(r)?,
+        max_price: get_decimal(r)?,
+        qty_step: get_decimal(r)?,
+        min_qty: get_decimal(r)?,
+        max_qty: get_decimal(r)?,
+        min_notional: get_decimal(r)?,
+        price_scale: get_u32(r)?,
+        qty_scale: get_u32(r)?,
+    })
+} //}}}
+
+// the order logic info is rebuilt when the order is inserted
+fn put_order<W: Write>(w: &mut W, order: &OrderInfo) -> Result<(), SnapshotError> {
+    //{{{
+    put_u64(w, order.id)?;
+    put_u64(w, order.uid)?;
+    put_u8(w, order.op as u8)?;
+    put_u8(w, order.time_in_force as u8)?;
+    put_u8(w, order.post_only as u8)?;
+    put_u8(w, order.qty_type as u8)?;
+    put_u8(w, order.side as u8)?;
+    put_u8(w, order.status as u8)?;
+    for qty in [
+        order.price,
+        order.trigger_price,
+        order.avg_trade_price,
+        order.raw_qty,
+        order.remain_qty,
+        order.display_qty,
+        order.hidden_qty,
+        order.trade_qty,
+        order.trade_oppo_qty,
+        order.taker_fee_rate,
+        order.maker_fee_rate,
+        order.fee,
+    ]
+    .iter()
+    {
+        put_decimal(w, *qty)?;
+    }
+    Ok(())
+} //}}}
+
+fn get_order<R: Read>(r: &mut R) -> Result<OrderInfo, SnapshotError> {
+    //{{{
+    Ok(OrderInfo {
+        id: get_u64(r)?,
+        uid: get_u64(r)?,
+        op: get_enum(r, &ORDER_OPS)?,
+        time_in_force: get_enum(r, &TIME_IN_FORCES)?,
+        post_only: get_u8(r)? != 0,
+        qty_type: get_enum(r, &QTY_TYPES)?,
+        side: get_enum(r, &ORDER_SIDES)?,
+        status: get_enum(r, &ORDER_STATUSES)?,
+        price: get_decimal(r)?,
+        trigger_price: get_decimal(r)?,
+        avg_trade_price: get_decimal(r)?,
+        raw_qty: get_decimal(r)?,
+        remain_qty: get_decimal(r)?,
+        display_qty: get_decimal(r)?,
+        hidden_qty: get_decimal(r)?,
+        trade_qty: get_decimal(r)?,
+        trade_oppo_qty: get_decimal(r)?,
+        taker_fee_rate: get_decimal(r)?,
+        maker_fee_rate: get_decimal(r)?,
+        fee: get_decimal(r)?,
+        logic: OrderLogic::default(),
+    })
+} //}}}
+
+fn put_u8<W: Write>(w: &mut W, v: u8) -> io::Result<()> {
+    w.write_all(&[v])
+}
+
+fn put_u16<W: Write>(w: &mut W, v: u16) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn put_u32<W: Write>(w: &mut W, v: u32) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn put_u64<W: Write>(w: &mut W, v: u64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+fn put_decimal<W: Write>(w: &mut W, v: Decimal) -> io::Result<()> {
+    w.write_all(&v.serialize())
+}
+
+fn put_str<W: Write>(w: &mut W, v: &str) -> Result<(), SnapshotError> {
+    //{{{
+    if v.len() > u16::MAX as usize {
+        return Err(SnapshotError::Corrupt(format!("string {} is too long", v)));
+    }
+    put_u16(w, v.len() as u16)?;
+    w.write_all(v.as_bytes())?;
+    Ok(())
+} //}}}
+
+fn get_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn get_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn get_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn get_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn get_decimal<R: Read>(r: &mut R) -> io::Result<Decimal> {
+    let mut buf = [0u8; 16];
+    r.read_exact(&mut buf)?;
+    Ok(Decimal::deserialize(buf))
+}
+
+fn get_str<R: Read>(r: &mut R) -> Result<String, SnapshotError> {
+    //{{{
+    let len = get_u16(r)? as usize;
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| SnapshotError::Corrupt("string is not utf8".to_owned()))
+} //}}}
+
+fn get_enum<R: Read, T: Copy>(r: &mut R, table: &[T]) -> Result<T, SnapshotError> {
+    //{{{
+    let index = get_u8(r)? as usize;
+    table
+        .get(index)
+        .cloned()
+        .ok_or_else(|| SnapshotError::Corrupt(format!("unknown enum index {}", index)))
+} //}}}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Msg;
+
+    #[test]
+    fn binary_snapshot_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(1000, "BTC/USDT".to_owned());
+        orderbook.set_market_config(MarketConfig {
+            base_asset: "BTC".to_owned(),
+            quote_asset: "USDT".to_owned(),
+            price_tick: dec!(0.01),
+            ..Default::default()
+        });
+        orderbook.set_self_trade_prevention(SelfTradePrevention::CancelOldest);
+        orderbook.set_snapshot_mode(SnapshotMode::Fork, "/tmp/snapshot");
+        orderbook.set_order_events(true);
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.apply(1, Msg::SimpleOrder(order));
+        order.id = 2;
+        order.display_qty = dec!(10);
+        orderbook.apply(2, Msg::SimpleOrder(order));
+        order.id = 3;
+        order.uid = 10002;
+        order.side = OrderSide::Ask;
+        order.display_qty = dec!(0);
+        order.price = dec!(1.19);
+        order.raw_qty = dec!(50);
+        order.remain_qty = dec!(50);
+        orderbook.apply(3, Msg::SimpleOrder(order));
+        order.id = 4;
+        order.price = dec!(1.3);
+        orderbook.apply(4, Msg::SimpleOrder(order));
+        order.id = 5;
+        order.op = OrderOp::StopLimit;
+        order.trigger_price = dec!(1.1);
+        orderbook.apply(5, Msg::SimpleOrder(order));
+
+        // the snapshot is streamed without seeking back
+        let mut buf = Vec::new();
+        let header = write(&orderbook, &mut buf).unwrap();
+        assert_eq!(header.seq, 5);
+        assert_eq!(header.market, "BTC/USDT");
+        assert_eq!(read_header(&mut &buf[..]).unwrap(), header);
+        // only the live orders are written
+        assert!(buf.len() < 4096);
+
+        let restored = read(&mut &buf[..]).unwrap();
+        assert_eq!(restored.seq(), 5);
+        assert_eq!(restored.orders.len(), 1000);
+        assert_eq!(restored.config, orderbook.config);
+        assert_eq!(restored.stp_mode, SelfTradePrevention::CancelOldest);
+        assert_eq!(restored.snapshot_mode, SnapshotMode::Fork);
+        assert_eq!(restored.snapshot_dir, "/tmp/snapshot");
+        assert!(restored.order_events_enabled);
+        assert_eq!(restored.last_price, dec!(1.2));
+        assert_eq!(restored.trade_seq(), orderbook.trade_seq());
+        assert_eq!(restored.bid_leader.qty, orderbook.bid_leader.qty);
+        assert_eq!(restored.ask_leader.price, dec!(1.3));
+        assert_eq!(restored.order_index.len(), 3);
+        assert_eq!(restored.ask_stop_index[&dec!(1.1)][0].id, 5);
+        assert!(restored.stop_order_index.contains_key(&5));
+        assert!(restored.check_consistency().is_ok());
+
+        // the queue order is kept, the snapshot of the restored orderbook is the same
+        let mut rebuf = Vec::new();
+        write(&restored, &mut rebuf).unwrap();
+        assert_eq!(rebuf, buf);
+
+        // the body, the trailer and the seq in the header are all checked
+        let seq_offset = 4 + 2 + 2 + header.market.len();
+        for offset in [seq_offset, buf.len() / 2, buf.len() - 1].iter() {
+            let mut corrupt = buf.clone();
+            corrupt[*offset] ^= 1;
+            match read(&mut &corrupt[..]) {
+                Err(SnapshotError::Corrupt(_)) => {}
+                _ => panic!("the corrupt snapshot is loaded"),
+            }
+        }
+        match read(&mut &buf[..buf.len() - 1]) {
+            Err(SnapshotError::Corrupt(_)) => {}
+            _ => panic!("the truncated snapshot is loaded"),
+        }
+
+        let mut newer = buf.clone();
+        newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        match read(&mut &newer[..]) {
+            Err(SnapshotError::UnsupportedVersion(version)) => {
+                assert_eq!(version, FORMAT_VERSION + 1)
+            }
+            _ => panic!("the newer snapshot is loaded"),
+        }
+
+        match read(&mut &b"{\"market\":\"BTC/USDT\"}"[..]) {
+            Err(SnapshotError::BadMagic) => {}
+            _ => panic!("the json snapshot is loaded"),
+        }
+    } //}}}
+}
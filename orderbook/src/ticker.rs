@@ -0,0 +1,174 @@
+use crate::kline::{Kline, KlineInterval};
+use crate::{MatchResult, OrderBook};
+use order::proto::{TradeRecord, TradeType};
+use rust_decimal::prelude::Zero;
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+pub const TICKER_WINDOW: u64 = 24 * 60 * 60 * 1000; // rolling window in milliseconds
+
+/// The rolling statistics of one market
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Ticker {
+    pub last_price: Decimal,           // the last trade price
+    pub best_bid: Decimal,             // best buy price, 0 if there is no buy order
+    pub best_bid_qty: Decimal,         // visible qty at the best buy price
+    pub best_ask: Decimal,             // best sell price, 0 if there is no sell order
+    pub best_ask_qty: Decimal,         // visible qty at the best sell price
+    pub open: Decimal,                 // first trade price in the window
+    pub high: Decimal,                 // highest trade price in the window
+    pub low: Decimal,                  // lowest trade price in the window
+    pub volume: Decimal,               // traded base qty in the window
+    pub quote_volume: Decimal,         // traded quote qty in the window
+    pub price_change: Decimal,         // last price - open price
+    pub price_change_percent: Decimal, // price change / open price * 100
+    pub trade_count: u64,              // number of trades in the window
+    pub open_time: u64,                // start of the window in milliseconds
+    pub close_time: u64,               // end of the window in milliseconds
+}
+
+/// Keeps the trades of the last 24 hours in 1 minute buckets,
+/// so the window slides by whole minutes
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RollingTicker {
+    last_price: Decimal,      // the last trade price, kept after the window is empty
+    buckets: VecDeque<Kline>, // 1 minute candles in time order
+}
+
+impl RollingTicker {
+    pub fn new() -> RollingTicker {
+        Default::default()
+    }
+
+    /// feed the trade record, the cancel and reject records are ignored
+    pub fn update(&mut self, record: &TradeRecord) {
+        //{{{
+        if record.trade_type != TradeType::SimpleTrade {
+            return;
+        }
+        self.last_price = record.trade_price;
+        match self.buckets.back_mut() {
+            // the record older than the last bucket is counted into the last bucket
+            Some(bucket) if record.time_stamp < bucket.close_time => bucket.update(record),
+            _ => self
+                .buckets
+                .push_back(Kline::new(KlineInterval::Min1, record)),
+        }
+        self.evict(record.time_stamp);
+    } //}}}
+
+    /// feed the trade records of the match result and its triggered stop orders
+    pub fn update_result(&mut self, result: &MatchResult) {
+        //{{{
+        for record in result.records() {
+            self.update(record);
+        }
+    } //}}}
+
+    // drop the buckets which are out of the window ending at now
+    fn evict(&mut self, now: u64) {
+        //{{{
+        let open_time = now.saturating_sub(TICKER_WINDOW);
+        while let Some(bucket) = self.buckets.front() {
+            if bucket.close_time > open_time {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    } //}}}
+
+    /// the statistics of the window ending at now with the best prices of the orderbook
+    pub fn ticker(&mut self, orderbook: &OrderBook, now: u64) -> Ticker {
+        //{{{
+        self.evict(now);
+        let mut ticker = Ticker {
+            last_price: self.last_price,
+            best_bid: orderbook.bid_leader.price,
+            best_bid_qty: orderbook.bid_leader.qty,
+            best_ask: orderbook.ask_leader.price,
+            best_ask_qty: orderbook.ask_leader.qty,
+            open_time: now.saturating_sub(TICKER_WINDOW),
+            close_time: now,
+            ..Default::default()
+        };
+
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            if i == 0 {
+                ticker.open = bucket.open;
+                ticker.high = bucket.high;
+                ticker.low = bucket.low;
+            }
+            if bucket.high > ticker.high {
+                ticker.high = bucket.high;
+            }
+            if bucket.low < ticker.low {
+                ticker.low = bucket.low;
+            }
+            ticker.volume += bucket.volume;
+            ticker.quote_volume += bucket.quote_volume;
+            ticker.trade_count += bucket.trade_count;
+        }
+
+        if !ticker.open.is_zero() {
+            ticker.price_change = ticker.last_price - ticker.open;
+            ticker.price_change_percent = ticker.price_change / ticker.open * dec!(100);
+        }
+        ticker
+    } //}}}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kline::tests::record;
+    use order::proto::{OrderInfo, OrderSide};
+
+    #[test]
+    fn rolling_ticker_test() {
+        //{{{
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        orderbook.match_entry(&mut order).unwrap();
+
+        let mut ticker = RollingTicker::new();
+        ticker.update(&record(1000, dec!(10), dec!(1)));
+        ticker.update(&record(2 * 3600 * 1000, dec!(15), dec!(2)));
+        ticker.update(&record(3 * 3600 * 1000, dec!(8), dec!(1)));
+        ticker.update(&record(4 * 3600 * 1000, dec!(12), dec!(1)));
+
+        let stats = ticker.ticker(&orderbook, 5 * 3600 * 1000);
+        assert_eq!(stats.best_bid, dec!(1.2));
+        assert_eq!(stats.best_bid_qty, dec!(100));
+        assert!(stats.best_ask.is_zero());
+        assert_eq!(stats.last_price, dec!(12));
+        assert_eq!(stats.open, dec!(10));
+        assert_eq!(stats.high, dec!(15));
+        assert_eq!(stats.low, dec!(8));
+        assert_eq!(stats.volume, dec!(5));
+        assert_eq!(stats.quote_volume, dec!(60));
+        assert_eq!(stats.trade_count, 4);
+        assert_eq!(stats.price_change, dec!(2));
+        assert_eq!(stats.price_change_percent, dec!(20));
+
+        // the first trade slides out of the window
+        let stats = ticker.ticker(&orderbook, TICKER_WINDOW + 60 * 1000);
+        assert_eq!(stats.open, dec!(15));
+        assert_eq!(stats.trade_count, 3);
+        assert_eq!(stats.price_change_percent, dec!(-20));
+
+        // the last price is kept after all trades slide out
+        let stats = ticker.ticker(&orderbook, 2 * TICKER_WINDOW);
+        assert_eq!(stats.trade_count, 0);
+        assert_eq!(stats.last_price, dec!(12));
+        assert!(stats.price_change.is_zero());
+    } //}}}
+}
@@ -1,7 +1,179 @@
+use orderbook::snapshot::{self, SnapshotError};
+use orderbook::{journal, snapshot_date, OrderBook};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum RestoreError {
+    SnapshotNotFound,        // there is no snapshot of the market
+    SnapshotIllegal(String), // the snapshot can not be loaded as the orderbook of the market
+    Inconsistent(String),    // the rebuilt orderbook is inconsistent
+    Io(io::Error),           // reading the snapshot or the journal failed
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestoreError::SnapshotNotFound => write!(f, "snapshot not found"),
+            RestoreError::SnapshotIllegal(msg) => write!(f, "snapshot illegal: {}", msg),
+            RestoreError::Inconsistent(msg) => write!(f, "orderbook inconsistent: {}", msg),
+            RestoreError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RestoreError {}
+
+impl From<io::Error> for RestoreError {
+    fn from(err: io::Error) -> RestoreError {
+        RestoreError::Io(err)
+    }
+}
+
+/// find the latest `<date>_<market>.d` snapshot written by `OrderBook::snapshot` in the dir
+pub fn latest_snapshot(dir: &Path, market: &str) -> Result<PathBuf, RestoreError> {
+    //{{{
+    let mut latest: Option<(String, String)> = None; // (date, file name)
+    for entry in fs::read_dir(dir)? {
+        let name = match entry?.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let date = match snapshot_date(&name, market) {
+            Some(date) => date.to_owned(),
+            None => continue,
+        };
+        // the date is %Y-%m-%d, the latest one is the largest one
+        if latest.as_ref().map_or(true, |(latest, _)| date > *latest) {
+            latest = Some((date, name));
+        }
+    }
+    latest
+        .map(|(_, name)| dir.join(name))
+        .ok_or(RestoreError::SnapshotNotFound)
+} //}}}
+
+/// load the orderbook from the snapshot file, the corrupt or newer version snapshot is rejected
+pub fn load_snapshot(path: &Path) -> Result<OrderBook, RestoreError> {
+    //{{{
+    let mut reader = BufReader::new(File::open(path)?);
+    snapshot::read(&mut reader).map_err(|err| match err {
+        SnapshotError::Io(err) => RestoreError::Io(err),
+        err => RestoreError::SnapshotIllegal(err.to_string()),
+    })
+} //}}}
+
+/// replay the journal entries after the sequence number of the orderbook,
+/// then check the rebuilt orderbook. the missing journal means nothing to replay
+pub fn replay_journal(orderbook: &mut OrderBook, journal_path: &Path) -> Result<(), RestoreError> {
+    //{{{
+    if journal_path.exists() {
+        // the results have been sent before the crash
+        journal::replay(orderbook, journal_path, |_| {})?;
+    }
+    orderbook
+        .check_consistency()
+        .map_err(RestoreError::Inconsistent)
+} //}}}
+
+/// rebuild the orderbook of the market from the latest snapshot in the dir and the journal tail
+pub fn restore(dir: &Path, market: &str, journal_path: &Path) -> Result<OrderBook, RestoreError> {
+    //{{{
+    let mut orderbook = load_snapshot(&latest_snapshot(dir, market)?)?;
+    if orderbook.market() != market {
+        return Err(RestoreError::SnapshotIllegal(format!(
+            "market {} is not {}",
+            orderbook.market(),
+            market
+        )));
+    }
+    replay_journal(&mut orderbook, journal_path)?;
+    Ok(orderbook)
+} //}}}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use order::proto::{OrderInfo, OrderSide};
+    use orderbook::journal::Journal;
+    use orderbook::Msg;
+    use rust_decimal_macros::*;
+    use std::io::Cursor;
+
     #[test]
-    fn it_works() {
-        assert_eq!(2 + 2, 4);
-    }
+    fn restore_test() {
+        //{{{
+        let dir = std::env::temp_dir().join(format!("restore_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let journal_path = dir.join("BTC_USDT.j");
+
+        let mut orderbook = OrderBook::new(100, "BTC/USDT".to_owned());
+        let mut journal = Journal::open(&journal_path, 1).unwrap();
+        let mut apply = |orderbook: &mut OrderBook, msg: Msg| {
+            let seq = journal.append(&msg, 0).unwrap();
+            orderbook.apply_at(seq, 0, msg);
+        };
+
+        let mut order = OrderInfo::new(
+            1,
+            10001,
+            OrderSide::Bid,
+            dec!(100),
+            dec!(1.2),
+            (dec!(0), dec!(0)),
+        );
+        apply(&mut orderbook, Msg::SimpleOrder(order));
+        order.id = 2;
+        order.price = dec!(1.21);
+        apply(&mut orderbook, Msg::SimpleOrder(order));
+        orderbook.dump(&dir).unwrap();
+
+        // the journal tail after the snapshot
+        order.id = 3;
+        order.uid = 10002;
+        order.side = OrderSide::Ask;
+        order.raw_qty = dec!(150);
+        order.remain_qty = dec!(150);
+        apply(&mut orderbook, Msg::SimpleOrder(order));
+        apply(&mut orderbook, Msg::CancelOrder((1, 10001)));
+
+        // the older snapshot and the snapshots of other markets are ignored,
+        // "BTC_USDT" and "USDT" do not share the file names of "BTC/USDT"
+        fs::write(dir.join("2000-01-01_BTC%2FUSDT.d"), b"{}").unwrap();
+        for market in ["ETH/USDT", "BTC_USDT", "USDT"].iter() {
+            let name = orderbook::snapshot_file_name("2999-01-01", market);
+            fs::write(dir.join(name), b"{}").unwrap();
+        }
+        fs::write(dir.join("latest_BTC%2FUSDT.d"), b"{}").unwrap();
+
+        let restored = restore(&dir, "BTC/USDT", &journal_path).unwrap();
+        assert_eq!(restored.seq(), 4);
+        assert_eq!(restored.trade_seq(), orderbook.trade_seq());
+        let mut restored_snapshot = Cursor::new(Vec::new());
+        snapshot::write(&restored, &mut restored_snapshot).unwrap();
+        let mut expected_snapshot = Cursor::new(Vec::new());
+        snapshot::write(&orderbook, &mut expected_snapshot).unwrap();
+        assert_eq!(
+            restored_snapshot.into_inner(),
+            expected_snapshot.into_inner()
+        );
+
+        match restore(&dir, "ETH/BTC", &journal_path) {
+            Err(RestoreError::SnapshotNotFound) => {}
+            _ => panic!("the snapshot of ETH/BTC does not exist"),
+        }
+        assert_eq!(
+            latest_snapshot(&dir, "USDT").unwrap(),
+            dir.join("2999-01-01_USDT.d")
+        );
+        assert_eq!(
+            latest_snapshot(&dir, "BTC_USDT").unwrap(),
+            dir.join("2999-01-01_BTC%5FUSDT.d")
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    } //}}}
 }